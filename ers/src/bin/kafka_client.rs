@@ -1,8 +1,26 @@
-use clap::Parser;
-use ers::kafka;
+//! Producer benchmark, e.g., to size Kafka for a new site.
+//!
+//! Sends records of the given size at the target rate until the number of records is sent or
+//! the duration has elapsed, then flushes the producer and reports the throughput and the
+//! delivery latency percentiles, measured from enqueueing a record to its acknowledgement. The
+//! percentiles are estimated from the latency histograms of the producer metrics. The time spent
+//! waiting for room in the full producer queue is not part of the latency, it is reported on its
+//! own.
+//!
+//! ```sh
+//! example_kafka --broker localhost:9092 --topic bench --message-size 1024 --rate 10000 --duration 60 --acks all
+//! ```
+
+use clap::{Parser, ValueEnum};
+use ers::{kafka, logging};
 
 use rand::{distributions, Rng};
-use std::{collections::HashMap, thread::sleep, time::Duration};
+use std::{
+    collections::HashMap,
+    process,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 fn generate_message(length: usize) -> String {
     let rng = rand::thread_rng();
@@ -13,50 +31,177 @@ fn generate_message(length: usize) -> String {
         .collect()
 }
 
-fn send(producer: &kafka::KafkaProducer, payload: &String) {
-    let message = kafka::ProducerRecord {
-        key: "test".into(),
-        topic: String::from("dev-test"),
-        headers: HashMap::new(),
-        payload: payload.as_bytes().into(),
-    };
+#[derive(Clone, Copy, ValueEnum)]
+enum KeyDistribution {
+    /// The same key for every record, so all go to one partition
+    Fixed,
+    /// The keys in turn
+    RoundRobin,
+    /// A uniformly random key for each record
+    Random,
+}
 
-    match producer.send(&message) {
-        Ok(_) => (),
-        Err((e, _)) => log::error!("{}", e),
+#[derive(Clone, Copy, ValueEnum)]
+enum AcksArg {
+    #[value(name = "0")]
+    None,
+    #[value(name = "1")]
+    Leader,
+    All,
+}
+
+impl From<AcksArg> for kafka::Acks {
+    fn from(acks: AcksArg) -> Self {
+        match acks {
+            AcksArg::None => kafka::Acks::None,
+            AcksArg::Leader => kafka::Acks::Leader,
+            AcksArg::All => kafka::Acks::All,
+        }
     }
 }
 
 #[derive(clap::Parser)]
 struct Args {
-    /// Number of messages to send to Kafka broker
-    #[arg(short, long, default_value_t = 1)]
-    num_records: u64,
+    /// Number of messages to send to Kafka broker, 1 by default unless a duration is given
+    #[arg(short, long)]
+    num_records: Option<u64>,
+
+    /// How long to send in seconds
+    #[arg(short, long)]
+    duration: Option<f64>,
 
     /// The address of the Kafka broker
     #[arg(short, long, default_value_t = String::from("localhost:9092"))]
     broker: String,
+
+    #[arg(short, long, default_value_t = String::from("dev-test"))]
+    topic: String,
+
+    /// Size of the payload in bytes
+    #[arg(short = 's', long, default_value_t = 300)]
+    message_size: usize,
+
+    #[arg(long, value_enum, default_value_t = KeyDistribution::Fixed)]
+    key_distribution: KeyDistribution,
+
+    /// The number of distinct keys
+    #[arg(long, default_value_t = 100)]
+    keys: usize,
+
+    /// A header of each record as `key=value`, can be repeated
+    #[arg(long = "header", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Target rate in records per second, unlimited if 0
+    #[arg(short, long, default_value_t = 0)]
+    rate: u64,
+
+    #[arg(long, value_enum, default_value_t = AcksArg::All)]
+    acks: AcksArg,
+
+    /// How long to wait for the outstanding deliveries at the end in seconds
+    #[arg(long, default_value_t = 30)]
+    flush_timeout: u64,
+}
+
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    header
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value: {}", header))
 }
 
 fn main() {
-    env_logger::init();
     let args = Args::parse();
+    logging::init(&logging::LogSettings::default(), "example_kafka").expect("Failed to initialize logging");
+
+    let producer = kafka::KafkaProducer::builder()
+        .brokers([args.broker])
+        .acks(args.acks.into())
+        .queue_full_policy(kafka::QueueFullPolicy::Block { timeout: Duration::from_secs(10) })
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to create the producer: {}", err);
+            process::exit(1);
+        });
+
+    let keys = match args.key_distribution {
+        KeyDistribution::Fixed => vec![String::from("test")],
+        _ => (0..args.keys.max(1)).map(|i| format!("key-{}", i)).collect(),
+    };
+    let mut message = kafka::ProducerRecord {
+        key: keys[0].as_str(),
+        topic: args.topic,
+        headers: args.headers.into_iter().map(|(key, value)| (key, value.into_bytes())).collect::<HashMap<_, _>>(),
+        payload: generate_message(args.message_size),
+    };
+
+    let limit = args.num_records.or(if args.duration.is_some() { None } else { Some(1) });
+    let duration = args.duration.map(Duration::from_secs_f64);
+    let interval = (args.rate > 0).then(|| Duration::from_secs_f64(1.0 / args.rate as f64));
+    let mut rng = rand::thread_rng();
+
+    let start = Instant::now();
+    let mut sent = 0u64;
+    let mut failed = 0u64;
+    let mut sending = Duration::ZERO;
+    while limit.is_none_or(|limit| sent + failed < limit) && duration.is_none_or(|x| start.elapsed() < x) {
+        if let Some(interval) = interval {
+            let due = start + interval.mul_f64((sent + failed) as f64);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                sleep(wait);
+            }
+        }
 
-    let mut cfg = HashMap::new();
-    cfg.insert("bootstrap.servers".to_string(), args.broker);
-    let producer = kafka::KafkaProducer::new(cfg);
+        message.key = match args.key_distribution {
+            KeyDistribution::Fixed => &keys[0],
+            KeyDistribution::RoundRobin => &keys[((sent + failed) % keys.len() as u64) as usize],
+            KeyDistribution::Random => &keys[rng.gen_range(0..keys.len())],
+        };
 
-    let message = generate_message(300);
+        let enqueueing = Instant::now();
+        let result = producer.send(&message);
+        sending += enqueueing.elapsed();
+        match result {
+            Ok(_) => sent += 1,
+            Err(e) => {
+                log::error!("{}", e);
+                failed += 1;
+            }
+        }
+    }
+    let enqueued = start.elapsed();
 
-    let time = std::time::Instant::now();
-    for _ in 1..=args.num_records {
-        send(&producer, &message);
+    if let Err(err) = producer.flush(Duration::from_secs(args.flush_timeout)) {
+        log::error!("Failed to flush the producer: {}", err);
     }
+    let elapsed = start.elapsed();
 
-    log::info!("Elapsed time: {:?}", time.elapsed());
+    let snapshot = producer.snapshot();
+    let mut latency = kafka::LatencyHistogram::default();
+    for partition in &snapshot.partitions {
+        latency.merge(&partition.latency);
+    }
 
-    // Producer's `send` is async function call, which is most likely slower than
-    // this thread reaching the debug log statement
-    sleep(Duration::new(0, 200_000_000));
-    log::debug!("{:?}", producer.metrics());
+    let seconds = elapsed.as_secs_f64();
+    println!("Records:    {} enqueued, {} not enqueued", sent, failed);
+    println!("Delivered:  {} records, {} failed", snapshot.sent_messages, snapshot.dropped_messages);
+    println!("Time:       {:.3} s sending, {:.3} s with flush", enqueued.as_secs_f64(), seconds);
+    println!("Queue full: {} times, {:.3} s in send", snapshot.queue_full, sending.as_secs_f64());
+    println!(
+        "Throughput: {:.0} records/s, {:.2} MB/s",
+        snapshot.sent_messages as f64 / seconds,
+        snapshot.sent_bytes as f64 / seconds / 1_000_000.0,
+    );
+    println!(
+        "Latency:    p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, p99.9 {:.2?}, max {:.2?}",
+        latency.percentile(0.5),
+        latency.percentile(0.9),
+        latency.percentile(0.99),
+        latency.percentile(0.999),
+        Duration::from_micros(latency.max_us),
+    );
+    for (code, count) in &snapshot.errors {
+        println!("Errors:     {} x {}", count, code);
+    }
 }
@@ -0,0 +1,198 @@
+//! Prints the records of a topic, e.g., to inspect it where the Kafka command line tools are not
+//! available.
+//!
+//! All partitions are read from the given position without joining a consumer group, so the
+//! committed offsets of the services are not affected.
+//!
+//! ```sh
+//! kafka_tail --broker localhost:9092 --topic output_1 --from earliest --header ves-version=v7.2.1 --pretty -n 10
+//! ```
+
+use std::{process, str::FromStr, time::Duration};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Parser;
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{BaseConsumer, Consumer},
+    message::{BorrowedMessage, Message},
+    Offset, TopicPartitionList,
+};
+
+use ers::{kafka::ConsumerRecord, logging};
+
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to start reading each partition.
+#[derive(Clone, Copy, Debug)]
+enum Start {
+    Earliest,
+    Latest,
+    /// The first record at or after the time, in milliseconds since the epoch
+    Timestamp(i64),
+}
+
+impl FromStr for Start {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "earliest" => Ok(Start::Earliest),
+            "latest" => Ok(Start::Latest),
+            _ => DateTime::parse_from_rfc3339(value)
+                .map(|x| x.timestamp_millis())
+                .or_else(|_| value.parse::<i64>())
+                .map(Start::Timestamp)
+                .map_err(|_| format!("expected earliest, latest, RFC 3339 time or epoch milliseconds: {}", value)),
+        }
+    }
+}
+
+#[derive(clap::Parser)]
+struct Args {
+    /// The address of the Kafka broker
+    #[arg(short, long, default_value_t = String::from("localhost:9092"))]
+    broker: String,
+
+    #[arg(short, long)]
+    topic: String,
+
+    /// `earliest`, `latest`, or a time as RFC 3339 or milliseconds since the epoch
+    #[arg(short, long, default_value = "latest")]
+    from: Start,
+
+    /// Print only the records with this key
+    #[arg(short, long)]
+    key: Option<String>,
+
+    /// Print only the records with this header as `key=value`, can be repeated
+    #[arg(long = "header", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Indent JSON payloads
+    #[arg(short, long)]
+    pretty: bool,
+
+    /// Print the headers of the records
+    #[arg(long)]
+    print_headers: bool,
+
+    /// Exit after printing this many records
+    #[arg(short = 'n', long)]
+    max_messages: Option<u64>,
+}
+
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    header
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value: {}", header))
+}
+
+/// Assigns every partition of the topic, starting at `start`.
+fn assign(consumer: &BaseConsumer, topic: &str, start: Start) -> Result<(), String> {
+    let metadata = consumer
+        .fetch_metadata(Some(topic), METADATA_TIMEOUT)
+        .map_err(|err| format!("Failed to fetch the metadata of `{}`: {}", topic, err))?;
+    let partitions = metadata
+        .topics()
+        .first()
+        .filter(|x| x.error().is_none())
+        .map(|x| x.partitions().iter().map(|p| p.id()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if partitions.is_empty() {
+        return Err(format!("Topic `{}` does not exist", topic));
+    }
+
+    let offset = match start {
+        Start::Earliest => Offset::Beginning,
+        Start::Latest => Offset::End,
+        Start::Timestamp(time) => Offset::Offset(time),
+    };
+    let mut assignment = TopicPartitionList::new();
+    for partition in partitions {
+        assignment
+            .add_partition_offset(topic, partition, offset)
+            .map_err(|err| err.to_string())?;
+    }
+
+    if let Start::Timestamp(_) = start {
+        assignment = consumer
+            .offsets_for_times(assignment, METADATA_TIMEOUT)
+            .map_err(|err| format!("Failed to look up the offsets by time: {}", err))?;
+    }
+    consumer.assign(&assignment).map_err(|err| err.to_string())
+}
+
+fn matches(record: &ConsumerRecord, key: Option<&str>, headers: &[(String, String)]) -> bool {
+    key.is_none_or(|key| record.key.as_deref() == Some(key.as_bytes()))
+        && headers
+            .iter()
+            .all(|(name, value)| record.headers.get(name).is_some_and(|x| x == value.as_bytes()))
+}
+
+fn format_payload(payload: &[u8], pretty: bool) -> String {
+    match serde_json::from_slice::<serde_json::Value>(payload) {
+        Ok(json) if pretty => serde_json::to_string_pretty(&json).expect("JSON is always serializable"),
+        _ => String::from_utf8_lossy(payload).into_owned(),
+    }
+}
+
+fn print(msg: &BorrowedMessage, record: &ConsumerRecord, args: &Args) {
+    let time = msg
+        .timestamp()
+        .to_millis()
+        .and_then(DateTime::<Utc>::from_timestamp_millis)
+        .map(|x| x.to_rfc3339_opts(SecondsFormat::Millis, true))
+        .unwrap_or_else(|| String::from("-"));
+    let key = record.key.as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+    println!("--- {} partition {} offset {} time {} key {}", record.topic, record.partition, record.offset, time, key);
+
+    if args.print_headers {
+        let mut headers = record.headers.iter().collect::<Vec<_>>();
+        headers.sort();
+        for (name, value) in headers {
+            println!("{}: {}", name, String::from_utf8_lossy(value));
+        }
+    }
+    println!("{}", format_payload(record.payload.as_deref().unwrap_or_default(), args.pretty));
+}
+
+fn main() {
+    let args = Args::parse();
+    logging::init(&logging::LogSettings::default(), "kafka_tail").expect("Failed to initialize logging");
+
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &args.broker)
+        // Required by librdkafka, the offsets are never committed
+        .set("group.id", format!("kafka-tail-{}", process::id()))
+        .set("enable.auto.commit", "false")
+        .create()
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to create the consumer: {}", err);
+            process::exit(1);
+        });
+
+    if let Err(err) = assign(&consumer, &args.topic, args.from) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+
+    let mut printed = 0;
+    while args.max_messages.is_none_or(|max| printed < max) {
+        let msg = match consumer.poll(Duration::from_millis(500)) {
+            None => continue,
+            Some(Ok(msg)) => msg,
+            Some(Err(err)) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+
+        let record: ConsumerRecord = ConsumerRecord::from_message(&msg).expect("Bytes are always valid");
+        if matches(&record, args.key.as_deref(), &args.headers) {
+            print(&msg, &record, &args);
+            printed += 1;
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! Layered configuration loading for chassis-based services.
+//!
+//! [`ConfigLoader`] merges the following sources into any [`DeserializeOwned`] settings type,
+//! later sources overriding earlier ones:
+//! 1. defaults given by the service,
+//! 2. the configuration file, `<PREFIX>_CONFIG_FILE` or `./settings` with any supported extension,
+//! 3. a Kubernetes ConfigMap mounted as a directory, `<PREFIX>_CONFIG_DIR`,
+//! 4. environment variables `<PREFIX>_<SECTION>__<KEY>`.
+//!
+//! In the mounted directory, files with a known extension (e.g., `settings.yaml`) are merged as
+//! whole documents, while any other file is a single value whose dotted name is the key, e.g., the
+//! content of `kafka.address` sets `kafka.address`. Hidden entries, like the `..data` links
+//! created by Kubernetes, are skipped.
+//!
+//! ## Example
+//!
+//! ```
+//! use std::collections::HashMap;
+//! use serde::Deserialize;
+//! use ers::config::ConfigLoader;
+//!
+//! #[derive(Deserialize)]
+//! struct Settings {
+//!     port: u16,
+//! }
+//!
+//! let env = HashMap::from([("APP_PORT".to_string(), "9090".to_string())]);
+//! let settings: Settings = ConfigLoader::new("APP").env(env).load().unwrap();
+//! assert_eq!(settings.port, 9090);
+//! ```
+
+use std::{
+    collections::HashMap,
+    env,
+    fmt,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ::config::{Config, Environment, File, FileFormat};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+const DEFAULT_FILE: &str = "./settings";
+const ENV_SEPARATOR: &str = "__";
+
+/// Replacement of secret values in [`redacted`] output.
+pub const REDACTED: &str = "***";
+
+/// Keys containing any of these (ignoring case and `_`, `-`, `.`) are considered secrets.
+const SECRET_MARKERS: &[&str] = &["password", "passwd", "secret", "token", "credential", "privatekey", "apikey"];
+
+/// Every problem found while loading or validating the configuration.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl ConfigError {
+    pub fn new(error: impl Into<String>) -> Self {
+        Self { errors: vec![error.into()] }
+    }
+
+    /// Converts the collected errors into a `Result`, `Ok` if there was none.
+    pub fn from_errors(errors: Vec<String>) -> Result<(), Self> {
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Self { errors })
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for error in &self.errors {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<::config::ConfigError> for ConfigError {
+    fn from(value: ::config::ConfigError) -> Self {
+        Self::new(value.to_string())
+    }
+}
+
+/// Builder of the configuration sources, see the [module documentation](self).
+pub struct ConfigLoader {
+    prefix: String,
+    defaults: Option<Config>,
+    file: Option<PathBuf>,
+    dir: Option<PathBuf>,
+    env: Option<HashMap<String, String>>,
+}
+
+impl ConfigLoader {
+    /// Creates a loader reading the environment variables starting with `<prefix>_`.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            defaults: None,
+            file: None,
+            dir: None,
+            env: None,
+        }
+    }
+
+    /// Lowest priority values, typically `Settings::default()`.
+    pub fn defaults<T: Serialize>(mut self, defaults: &T) -> Result<Self, ConfigError> {
+        self.defaults = Some(Config::try_from(defaults)?);
+        Ok(self)
+    }
+
+    /// Configuration file, overrides `<PREFIX>_CONFIG_FILE`.
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// ConfigMap mounted directory, overrides `<PREFIX>_CONFIG_DIR`.
+    pub fn dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dir = Some(path.into());
+        self
+    }
+
+    /// Environment variables to use instead of the ones of the process.
+    pub fn env(mut self, vars: HashMap<String, String>) -> Self {
+        self.env = Some(vars);
+        self
+    }
+
+    /// Merges all sources and deserializes the result.
+    pub fn load<T: DeserializeOwned>(self) -> Result<T, ConfigError> {
+        let vars = self.env.unwrap_or_else(|| env::vars().collect());
+        let file_var = format!("{}_CONFIG_FILE", self.prefix);
+        let dir_var = format!("{}_CONFIG_DIR", self.prefix);
+
+        let mut builder = Config::builder();
+
+        if let Some(defaults) = self.defaults {
+            builder = builder.add_source(defaults);
+        }
+
+        builder = match self.file.or_else(|| vars.get(&file_var).map(PathBuf::from)) {
+            Some(path) => builder.add_source(File::with_name(&path.to_string_lossy())),
+            None => builder.add_source(File::with_name(DEFAULT_FILE).required(false)),
+        };
+
+        if let Some(dir) = self.dir.or_else(|| vars.get(&dir_var).map(PathBuf::from)) {
+            builder = builder.add_source(read_dir(&dir)?);
+        }
+
+        let overrides = vars
+            .into_iter()
+            .filter(|(key, _)| *key != file_var && *key != dir_var)
+            .collect();
+
+        Ok(builder
+            .add_source(
+                Environment::with_prefix(&self.prefix)
+                    .prefix_separator("_")
+                    .separator(ENV_SEPARATOR)
+                    .try_parsing(true)
+                    .source(Some(overrides))
+            )
+            .build()?
+            .try_deserialize()?)
+    }
+}
+
+fn read_dir(dir: &Path) -> Result<Config, ConfigError> {
+    let entries = fs::read_dir(dir)
+        .map_err(|err| ConfigError::new(format!("Failed to read config directory {}: {}", dir.display(), err)))?;
+
+    let mut files = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| !is_hidden(path) && path.is_file())
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let mut builder = Config::builder();
+    let mut errors = Vec::new();
+
+    for path in files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let format = path
+            .extension()
+            .and_then(|ext| file_format(&ext.to_string_lossy()));
+
+        if let Some(format) = format {
+            builder = builder.add_source(File::from(path).format(format));
+            continue;
+        }
+
+        match fs::read_to_string(&path) {
+            Ok(value) => builder = builder.set_override(name.as_str(), value.trim_end().to_string())?,
+            Err(err) => errors.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+
+    ConfigError::from_errors(errors)?;
+    Ok(builder.build()?)
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+fn file_format(extension: &str) -> Option<FileFormat> {
+    match extension {
+        "yaml" | "yml" => Some(FileFormat::Yaml),
+        "json" => Some(FileFormat::Json),
+        "toml" => Some(FileFormat::Toml),
+        _ => None,
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | '.'))
+        .collect::<String>()
+        .to_lowercase();
+
+    SECRET_MARKERS.iter().any(|marker| key.contains(marker))
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret(key) && !value.is_object() {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// The settings as JSON with the value of secret-like keys (e.g., `password`) replaced.
+///
+/// ## Example
+///
+/// ```
+/// use serde_json::json;
+/// use ers::config::redacted;
+///
+/// let settings = json!({ "kafka": { "address": "kf:9092", "sasl_password": "hunter2" } });
+/// assert_eq!(
+///     redacted(&settings).unwrap(),
+///     json!({ "kafka": { "address": "kf:9092", "sasl_password": "***" } })
+/// );
+/// ```
+pub fn redacted<T: Serialize>(settings: &T) -> Result<Value, serde_json::Error> {
+    let mut value = serde_json::to_value(settings)?;
+    redact(&mut value);
+    Ok(value)
+}
+
+/// Pretty-printed [`redacted`] settings for logging the effective configuration.
+pub fn dump_redacted<T: Serialize>(settings: &T) -> String {
+    redacted(settings)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|err| format!("<failed to serialize configuration: {}>", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    #[serde(default)]
+    struct Kafka {
+        address: String,
+        password: String,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    #[serde(default)]
+    struct Settings {
+        port: u16,
+        kafka: Kafka,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self { port: 8080, kafka: Kafka::default() }
+        }
+    }
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_layering() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("settings.yaml");
+        fs::write(&file, "port: 1\nkafka:\n  address: file:9092\n  password: file\n").unwrap();
+
+        let mounted = dir.path().join("mounted");
+        fs::create_dir(&mounted).unwrap();
+        fs::write(mounted.join("kafka.address"), "dir:9092\n").unwrap();
+        fs::write(mounted.join("..data"), "ignored").unwrap();
+
+        let settings: Settings = ConfigLoader::new("APP")
+            .defaults(&Settings::default()).unwrap()
+            .env(env(&[
+                ("APP_CONFIG_FILE", file.to_str().unwrap()),
+                ("APP_CONFIG_DIR", mounted.to_str().unwrap()),
+                ("APP_KAFKA__PASSWORD", "env"),
+                ("OTHER_PORT", "3"),
+            ]))
+            .load()
+            .unwrap();
+
+        assert_eq!(settings, Settings {
+            port: 1,
+            kafka: Kafka { address: "dir:9092".into(), password: "env".into() },
+        });
+    }
+
+    #[test]
+    fn test_defaults_only() {
+        let settings: Settings = ConfigLoader::new("APP")
+            .defaults(&Settings::default()).unwrap()
+            .env(HashMap::new())
+            .load()
+            .unwrap();
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let result = ConfigLoader::new("APP")
+            .file("missing.yaml")
+            .env(HashMap::new())
+            .load::<Settings>();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_missing_dir() {
+        let result = ConfigLoader::new("APP")
+            .env(env(&[("APP_CONFIG_DIR", "/does/not/exist")]))
+            .load::<Settings>();
+        assert!(result.unwrap_err().errors[0].contains("/does/not/exist"));
+    }
+
+    #[test]
+    fn test_redacted() {
+        let settings = Settings {
+            port: 1,
+            kafka: Kafka { address: "kf:9092".into(), password: "hunter2".into() },
+        };
+        let dump = dump_redacted(&settings);
+        assert!(dump.contains("kf:9092"));
+        assert!(!dump.contains("hunter2"));
+        assert!(dump.contains(REDACTED));
+    }
+}
@@ -1,93 +1,609 @@
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::fmt;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use rdkafka::message::Header;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::Context;
+use rdkafka::message::{BorrowedMessage, Header, Headers, Message};
 use rdkafka::config::ClientConfig;
 use rdkafka::error::KafkaError;
 use rdkafka::message::OwnedHeaders;
-use rdkafka::producer::{BaseRecord, DeliveryResult, ProducerContext, ThreadedProducer};
-use rdkafka::ClientContext;
+use rdkafka::consumer::ConsumerGroupMetadata;
+use rdkafka::producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer};
+use rdkafka::statistics::Statistics;
+use rdkafka::types::RDKafkaErrorCode;
+use rdkafka::{ClientContext, TopicPartitionList};
+
+use crate::trace;
+
+pub mod avro;
+pub mod metrics;
+pub mod schema_registry;
+pub mod serialization;
+
+pub use avro::{Avro, AvroSchema};
+pub use metrics::{LatencyHistogram, Metrics, MetricsSnapshot, PartitionSnapshot};
+pub use schema_registry::{
+    CachedSchemaRegistry, HttpSchemaRegistry, MockSchemaRegistry, Registered, RegistryError, Schema,
+    SchemaRegistry, SchemaSerializer, SchemaType,
+};
+pub use serialization::{Deserializer, Framed, Json, SerializationError, Serializer};
 
 /**
  * A Kafka record for sending messages
  *
+ * The key and the payload are bytes by default, or any [`Serializer`].
+ *
  * Example:
  * ```rust,no_run
- *   let message = ers::kafka::ProducerRecord {
+ *   let message: ers::kafka::ProducerRecord = ers::kafka::ProducerRecord {
  *       key: "test".into(),
  *       topic: String::from("dev-test"),
  *       headers: std::collections::HashMap::new(),
  *       payload: b"payload".into()
  *   };
+ *
+ *   let event = ers::kafka::ProducerRecord {
+ *       key: "test",
+ *       topic: String::from("dev-test"),
+ *       headers: std::collections::HashMap::new(),
+ *       payload: ers::kafka::Json(serde_json::json!({ "id": 1 })),
+ *   };
  * ```
  */
-// TODO(feat): `ToBytes` trait?
-pub struct ProducerRecord {
-    pub key: Vec<u8>,
+pub struct ProducerRecord<K = Vec<u8>, V = Vec<u8>> {
+    pub key: K,
     pub topic: String,
     pub headers: HashMap<String, Vec<u8>>,
-    pub payload: Vec<u8>,
+    pub payload: V,
 }
 
 /**
- * Thread-safe counters for outgoing data
+ * A consumed Kafka record
+ *
+ * Example:
+ * ```rust,no_run
+ *   # fn run(msg: &rdkafka::message::BorrowedMessage) -> Result<(), ers::kafka::SerializationError> {
+ *   use ers::kafka::{ConsumerRecord, Json};
+ *
+ *   let record: ConsumerRecord<String, Json<serde_json::Value>> = ConsumerRecord::from_message(msg)?;
+ *   # Ok(())
+ *   # }
+ * ```
  */
-#[derive(Default, Debug)]
-pub struct Metrics {
-    /// The number of successfully sent messages
-    pub sent_messages: AtomicU64,
-    /// The number of successfully sent bytes (only the payload)
-    pub sent_bytes: AtomicU64,
-    /// The number of messages failed to send
-    pub dropped_messages: AtomicU64,
-    /// The number of bytes failed to send (only the payload)
-    pub dropped_bytes: AtomicU64,
+#[derive(Debug)]
+pub struct ConsumerRecord<K = Vec<u8>, V = Vec<u8>> {
+    pub key: Option<K>,
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub headers: HashMap<String, Vec<u8>>,
+    pub payload: Option<V>,
 }
 
+impl<K: Deserializer, V: Deserializer> ConsumerRecord<K, V> {
+    /// Deserializes the key and the payload of the message, missing ones are `None`.
+    pub fn from_message<M: Message>(msg: &M) -> Result<Self, SerializationError> {
+        let headers = msg
+            .headers()
+            .map(|headers| {
+                headers
+                    .iter()
+                    .map(|header| (header.key.to_string(), header.value.unwrap_or_default().to_vec()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            key: msg.key().map(K::deserialize).transpose()?,
+            topic: msg.topic().to_string(),
+            partition: msg.partition(),
+            offset: msg.offset(),
+            headers,
+            payload: msg.payload().map(V::deserialize).transpose()?,
+        })
+    }
+}
+
+/// A record which could not be delivered to the broker.
+pub struct FailedRecord {
+    pub error: KafkaError,
+    pub record: ProducerRecord,
+}
+
+/// Called for each record which could not be delivered, see [`KafkaProducerBuilder::on_delivery_failure`].
+pub type FailureHandler = Box<dyn Fn(FailedRecord) + Send + Sync>;
+
+/// The outcome of sending a record, see [`KafkaProducerBuilder::on_delivery`].
+pub struct DeliveryReport<'a> {
+    pub topic: &'a str,
+    pub partition: i32,
+    /// Time from enqueueing the record to the acknowledgement or the failure
+    pub latency: Duration,
+    /// The offset of the delivered record
+    pub result: Result<i64, &'a KafkaError>,
+}
+
+/// Called for each delivery report, see [`KafkaProducerBuilder::on_delivery`].
+pub type DeliveryHandler = Box<dyn Fn(&DeliveryReport) + Send + Sync>;
+
 struct CustomProducerContext {
     metrics: Arc<Metrics>,
+    on_failure: Option<FailureHandler>,
+    on_delivery: Option<DeliveryHandler>,
 }
 
 impl CustomProducerContext {
-    fn new() -> Self {
+    fn new(on_failure: Option<FailureHandler>, on_delivery: Option<DeliveryHandler>) -> Self {
         Self {
             metrics: Default::default(),
+            on_failure,
+            on_delivery,
         }
     }
 }
 
-impl ClientContext for CustomProducerContext {}
+/// Copies the message back into a record, e.g., to send it to another topic.
+fn to_record(msg: &BorrowedMessage) -> ProducerRecord {
+    let record = ConsumerRecord::<Vec<u8>, Vec<u8>>::from_message(msg).expect("Bytes are always valid");
+
+    ProducerRecord {
+        key: record.key.unwrap_or_default(),
+        topic: record.topic,
+        headers: record.headers,
+        payload: record.payload.unwrap_or_default(),
+    }
+}
+
+/// Keeps the statistics, emitted every `statistics.interval.ms`
+impl ClientContext for CustomProducerContext {
+    fn stats(&self, statistics: Statistics) {
+        self.metrics.record_statistics(statistics);
+    }
+}
 
 /**
- * Increments the counters stored in `Metrics`
+ * Updates the `Metrics`, the opaque of a message is the time it was enqueued
  */
 impl ProducerContext for CustomProducerContext {
-    type DeliveryOpaque = ();
-    fn delivery(&self, delivery_result: &DeliveryResult, _: Self::DeliveryOpaque) {
+    type DeliveryOpaque = Box<Instant>;
+    fn delivery(&self, delivery_result: &DeliveryResult, enqueued_at: Self::DeliveryOpaque) {
+        let latency = enqueued_at.elapsed();
+        if let Some(on_delivery) = &self.on_delivery {
+            let (msg, result) = match delivery_result {
+                Ok(msg) => (msg, Ok(msg.offset())),
+                Err((err, msg)) => (msg, Err(err)),
+            };
+            on_delivery(&DeliveryReport { topic: msg.topic(), partition: msg.partition(), latency, result });
+        }
+
         match delivery_result {
             Ok(msg) => {
-                self.metrics.sent_messages.fetch_add(1, Ordering::Relaxed);
-                self.metrics
-                    .sent_bytes
-                    .fetch_add(msg.payload_len() as u64, Ordering::Relaxed);
+                self.metrics.record_sent(msg.topic(), msg.partition(), msg.payload_len(), latency);
             }
             Err((err, msg)) => {
-                self.metrics
-                    .dropped_messages
-                    .fetch_add(1, Ordering::Relaxed);
-                self.metrics
-                    .dropped_bytes
-                    .fetch_add(msg.payload_len() as u64, Ordering::Relaxed);
-                dbg!(err);
+                self.metrics.record_dropped(msg.topic(), msg.partition(), msg.payload_len(), err);
+                log::warn!("Failed to deliver message to topic `{}`: {}", msg.topic(), err);
+
+                if let Some(on_failure) = &self.on_failure {
+                    on_failure(FailedRecord { error: err.clone(), record: to_record(msg) });
+                }
             }
         }
     }
 }
 
+/// The error of [`KafkaProducer::send`].
+#[derive(Debug)]
+pub enum SendError {
+    /// The key or the payload cannot be serialized.
+    Serialization(SerializationError),
+    /// The record cannot be enqueued, e.g., the queue is full.
+    Kafka(KafkaError),
+}
+
+impl SendError {
+    pub fn kafka_error(&self) -> Option<&KafkaError> {
+        match self {
+            SendError::Kafka(err) => Some(err),
+            SendError::Serialization(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Serialization(err) => write!(f, "Failed to serialize record: {}", err),
+            SendError::Kafka(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
 pub struct KafkaProducer {
     producer: ThreadedProducer<CustomProducerContext>,
     metrics: Arc<Metrics>,
+    transactional: bool,
+    queue_full_policy: QueueFullPolicy,
+}
+
+/// How long to poll the delivery reports before retrying a record while the queue is full.
+const QUEUE_FULL_POLL: Duration = Duration::from_millis(10);
+
+/// What [`KafkaProducer::send`] does when the local queue of `librdkafka` is full.
+///
+/// The queue is limited by `queue.buffering.max.messages` and `queue.buffering.max.kbytes`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum QueueFullPolicy {
+    /// Return the `QueueFull` error at once
+    FailFast,
+    /// Poll the delivery reports and retry until the timeout has elapsed
+    Block { timeout: Duration },
+    /// Retry after a backoff, doubled after each attempt up to `max_backoff`
+    Retry { retries: u32, initial_backoff: Duration, max_backoff: Duration },
+}
+
+impl QueueFullPolicy {
+    /// How long to wait before the next attempt, or `None` to give up.
+    fn wait(&self, attempt: u32, elapsed: Duration) -> Option<Duration> {
+        match *self {
+            QueueFullPolicy::FailFast => None,
+            QueueFullPolicy::Block { timeout } => timeout
+                .checked_sub(elapsed)
+                .filter(|left| !left.is_zero())
+                .map(|left| left.min(QUEUE_FULL_POLL)),
+            QueueFullPolicy::Retry { retries, initial_backoff, max_backoff } => (attempt < retries)
+                .then(|| initial_backoff.saturating_mul(1 << attempt.min(31)).min(max_backoff)),
+        }
+    }
+}
+
+/// Default timeout of initializing the transactions, see [`KafkaProducerBuilder::transaction_timeout`].
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The number of acknowledgements the leader broker must receive before answering a request.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Acks {
+    /// Fire and forget, no response is sent
+    None,
+    /// The leader has written the record to its log
+    Leader,
+    /// All in-sync replicas have the record, required by idempotence
+    All,
+}
+
+impl fmt::Display for Acks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Acks::None => write!(f, "0"),
+            Acks::Leader => write!(f, "1"),
+            Acks::All => write!(f, "all"),
+        }
+    }
+}
+
+/// Compression codec of the record batches.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Snappy => write!(f, "snappy"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// Protocol of the connections to the brokers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SecurityProtocol {
+    Plaintext,
+    Ssl,
+    SaslPlaintext,
+    SaslSsl,
+}
+
+impl fmt::Display for SecurityProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecurityProtocol::Plaintext => write!(f, "plaintext"),
+            SecurityProtocol::Ssl => write!(f, "ssl"),
+            SecurityProtocol::SaslPlaintext => write!(f, "sasl_plaintext"),
+            SecurityProtocol::SaslSsl => write!(f, "sasl_ssl"),
+        }
+    }
+}
+
+/// SASL authentication mechanism.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SaslMechanism {
+    Plain,
+    ScramSha256,
+    ScramSha512,
+}
+
+impl fmt::Display for SaslMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaslMechanism::Plain => write!(f, "PLAIN"),
+            SaslMechanism::ScramSha256 => write!(f, "SCRAM-SHA-256"),
+            SaslMechanism::ScramSha512 => write!(f, "SCRAM-SHA-512"),
+        }
+    }
+}
+
+/// Error of [`KafkaProducerBuilder::build`].
+#[derive(Debug)]
+pub enum ProducerConfigError {
+    /// The configuration is invalid, every problem is listed.
+    Invalid(Vec<String>),
+    /// The client cannot be created.
+    Kafka(KafkaError),
+    /// The transactions cannot be initialized.
+    Transaction(TransactionError),
+}
+
+impl fmt::Display for ProducerConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProducerConfigError::Invalid(errors) => {
+                write!(f, "Invalid Kafka producer configuration:")?;
+                for error in errors {
+                    write!(f, "\n  - {}", error)?;
+                }
+                Ok(())
+            }
+            ProducerConfigError::Kafka(err) => write!(f, "Failed to create Kafka producer: {}", err),
+            ProducerConfigError::Transaction(err) => write!(f, "Failed to initialize transactions: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ProducerConfigError {}
+
+/**
+ * Builder of [`KafkaProducer`]
+ *
+ * The typed setters cover the common options, any other `librdkafka` property can be set with
+ * [`KafkaProducerBuilder::property`]. The configuration is checked by [`KafkaProducerBuilder::build`].
+ *
+ * Example:
+ * ```rust,no_run
+ *   use std::time::Duration;
+ *   use ers::kafka::{Acks, Compression, KafkaProducerBuilder};
+ *
+ *   let producer = KafkaProducerBuilder::new()
+ *       .brokers(["kafka-0:9092", "kafka-1:9092"])
+ *       .acks(Acks::All)
+ *       .linger(Duration::from_millis(10))
+ *       .compression(Compression::Zstd)
+ *       .build()
+ *       .unwrap();
+ * ```
+ */
+pub struct KafkaProducerBuilder {
+    config: HashMap<String, String>,
+    on_failure: Option<FailureHandler>,
+    on_delivery: Option<DeliveryHandler>,
+    transaction_timeout: Duration,
+    queue_full_policy: QueueFullPolicy,
+}
+
+impl Default for KafkaProducerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KafkaProducerBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: HashMap::new(),
+            on_failure: None,
+            on_delivery: None,
+            transaction_timeout: TRANSACTION_TIMEOUT,
+            queue_full_policy: QueueFullPolicy::FailFast,
+        }
+    }
+
+    /// Sets a `librdkafka` configuration property.
+    pub fn property(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets all the `librdkafka` configuration properties of `dict`.
+    pub fn properties(mut self, dict: HashMap<String, String>) -> Self {
+        self.config.extend(dict);
+        self
+    }
+
+    /// The bootstrap brokers, e.g., `localhost:9092`.
+    pub fn brokers<I: IntoIterator<Item = S>, S: AsRef<str>>(self, brokers: I) -> Self {
+        let brokers = brokers.into_iter().map(|x| x.as_ref().to_string()).collect::<Vec<_>>();
+        self.property("bootstrap.servers", brokers.join(","))
+    }
+
+    pub fn acks(self, acks: Acks) -> Self {
+        self.property("acks", acks.to_string())
+    }
+
+    /// How long to wait for more records to fill a batch.
+    pub fn linger(self, linger: Duration) -> Self {
+        self.property("linger.ms", linger.as_millis().to_string())
+    }
+
+    /// Maximum size of a record batch in bytes.
+    pub fn batch_size(self, bytes: usize) -> Self {
+        self.property("batch.size", bytes.to_string())
+    }
+
+    pub fn compression(self, compression: Compression) -> Self {
+        self.property("compression.type", compression.to_string())
+    }
+
+    /// How many times a failed request is retried.
+    pub fn retries(self, retries: u32) -> Self {
+        self.property("retries", retries.to_string())
+    }
+
+    /// How long a record may wait for its delivery, including the retries.
+    pub fn message_timeout(self, timeout: Duration) -> Self {
+        self.property("message.timeout.ms", timeout.as_millis().to_string())
+    }
+
+    /// How often librdkafka emits its statistics, which are kept in the [`Metrics`].
+    pub fn statistics_interval(self, interval: Duration) -> Self {
+        self.property("statistics.interval.ms", interval.as_millis().to_string())
+    }
+
+    pub fn security_protocol(self, protocol: SecurityProtocol) -> Self {
+        self.property("security.protocol", protocol.to_string())
+    }
+
+    /// CA certificate to verify the brokers, in PEM format.
+    pub fn ssl_ca_location(self, path: impl Into<String>) -> Self {
+        self.property("ssl.ca.location", path)
+    }
+
+    /// Client certificate and private key for mutual TLS, in PEM format.
+    pub fn ssl_client_certificate(self, certificate: impl Into<String>, key: impl Into<String>) -> Self {
+        self.property("ssl.certificate.location", certificate)
+            .property("ssl.key.location", key)
+    }
+
+    pub fn sasl(self, mechanism: SaslMechanism, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.property("sasl.mechanism", mechanism.to_string())
+            .property("sasl.username", username)
+            .property("sasl.password", password)
+    }
+
+    /// Writes each message exactly once and in order to the partition, even if it is retried.
+    pub fn enable_idempotence(self, enable: bool) -> Self {
+        self.property("enable.idempotence", enable.to_string())
+    }
+
+    /// Makes the producer transactional, see [`KafkaProducer::begin_transaction`].
+    ///
+    /// The ID must be unique and stable across restarts of the same producer instance, so the
+    /// broker can fence off its zombie instances. It implies idempotence.
+    pub fn transactional_id(self, id: impl Into<String>) -> Self {
+        self.property("transactional.id", id)
+    }
+
+    /// Timeout of initializing the transactions at [`KafkaProducerBuilder::build`].
+    pub fn transaction_timeout(mut self, timeout: Duration) -> Self {
+        self.transaction_timeout = timeout;
+        self
+    }
+
+    /// What to do when the local queue is full, [`QueueFullPolicy::FailFast`] by default.
+    pub fn queue_full_policy(mut self, policy: QueueFullPolicy) -> Self {
+        self.queue_full_policy = policy;
+        self
+    }
+
+    /// Passes the undelivered records to `on_failure`.
+    ///
+    /// The handler is called from the polling thread of the producer, so it must not block.
+    pub fn on_delivery_failure(mut self, on_failure: impl Fn(FailedRecord) + Send + Sync + 'static) -> Self {
+        self.on_failure = Some(Box::new(on_failure));
+        self
+    }
+
+    /// Passes the report of every record, delivered or not, to `on_delivery`, e.g., to measure
+    /// the latency.
+    ///
+    /// The handler is called from the polling thread of the producer, so it must not block.
+    pub fn on_delivery(mut self, on_delivery: impl Fn(&DeliveryReport) + Send + Sync + 'static) -> Self {
+        self.on_delivery = Some(Box::new(on_delivery));
+        self
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.config.get(key).map(String::as_str)
+    }
+
+    /// Checks the configuration, including that `librdkafka` knows every property.
+    pub fn validate(&self) -> Result<(), ProducerConfigError> {
+        let mut errors = Vec::new();
+
+        if self.get("bootstrap.servers").is_none_or(|x| x.trim().is_empty()) {
+            errors.push("bootstrap.servers: no broker is given".to_string());
+        }
+
+        let idempotent = self.get("enable.idempotence") == Some("true") || self.config.contains_key("transactional.id");
+        if idempotent && self.get("acks").is_some_and(|x| x != "all" && x != "-1") {
+            errors.push("acks: must be `all` for an idempotent or transactional producer".to_string());
+        }
+        if self.config.contains_key("transactional.id") && self.get("enable.idempotence") == Some("false") {
+            errors.push("enable.idempotence: a transactional producer is always idempotent".to_string());
+        }
+
+        let protocol = self.get("security.protocol").unwrap_or("plaintext").to_ascii_lowercase();
+        if protocol.starts_with("sasl") && self.get("sasl.mechanism").is_some_and(|x| x != "GSSAPI")
+            && (self.get("sasl.username").is_none() || self.get("sasl.password").is_none())
+        {
+            errors.push("sasl.username, sasl.password: required by the SASL mechanism".to_string());
+        }
+        if self.config.contains_key("ssl.certificate.location") != self.config.contains_key("ssl.key.location") {
+            errors.push("ssl.certificate.location, ssl.key.location: both must be given".to_string());
+        }
+
+        let mut keys = self.config.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            let mut cfg = ClientConfig::new();
+            cfg.set(key, &self.config[key]);
+            if let Err(KafkaError::ClientConfig(_, reason, _, _)) = cfg.create_native_config() {
+                errors.push(format!("{}: {}", key, reason));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ProducerConfigError::Invalid(errors))
+        }
+    }
+
+    /// Validates the configuration and creates the producer, then initializes the transactions
+    /// if it is transactional.
+    pub fn build(self) -> Result<KafkaProducer, ProducerConfigError> {
+        self.validate()?;
+
+        let mut cfg = ClientConfig::new();
+        for (key, value) in &self.config {
+            cfg.set(key, value);
+        }
+
+        let context = CustomProducerContext::new(self.on_failure, self.on_delivery);
+        let metrics = context.metrics.clone();
+        let producer: ThreadedProducer<_> = cfg.create_with_context(context).map_err(ProducerConfigError::Kafka)?;
+
+        let transactional = self.config.contains_key("transactional.id");
+        if transactional {
+            producer
+                .init_transactions(self.transaction_timeout)
+                .map_err(|err| ProducerConfigError::Transaction(err.into()))?;
+        }
+
+        Ok(KafkaProducer { producer, metrics, transactional, queue_full_policy: self.queue_full_policy })
+    }
 }
 
 /**
@@ -96,40 +612,459 @@ pub struct KafkaProducer {
  * Underlying library is `librdkafka`. A separate thread calls the `poll()` periodically.
  */
 impl KafkaProducer {
+    /// Creates a producer from `librdkafka` properties, it panics if they are invalid.
+    #[deprecated(note = "use `KafkaProducerBuilder`, which reports invalid configuration as an error")]
     pub fn new(dict: HashMap<String, String>) -> Self {
-        let mut cfg = ClientConfig::new();
-        for (key, value) in &dict {
-            cfg.set(key, value);
-        }
+        KafkaProducerBuilder::new().properties(dict).build().unwrap()
+    }
 
-        // TODO: customizable context
-        let context = CustomProducerContext::new();
-        Self {
-            metrics: context.metrics.clone(),
-            producer: cfg.create_with_context(context).unwrap(),
+    pub fn builder() -> KafkaProducerBuilder {
+        KafkaProducerBuilder::new()
+    }
+
+    /// Starts a transaction, the records sent until it is committed are written atomically.
+    ///
+    /// Only one transaction can be active at a time.
+    pub fn begin_transaction(&self) -> Result<Transaction<'_>, TransactionError> {
+        if !self.transactional {
+            return Err(TransactionError::NotTransactional);
         }
+
+        self.producer.begin_transaction()?;
+        Ok(Transaction { producer: self, finished: false })
+    }
+
+    /// Waits until the queued records are delivered.
+    pub fn flush(&self, timeout: Duration) -> Result<(), KafkaError> {
+        self.producer.flush(timeout)
+    }
+
+    /// Enqueues the record for sending.
+    ///
+    /// The `kafka.send` span is the child of the trace context in the record headers, and its own
+    /// context replaces that in the headers sent to the broker.
+    ///
+    /// If the local queue is full, the [`QueueFullPolicy`] of the producer applies.
+    pub fn send<K: Serializer, V: Serializer>(&self, message: &ProducerRecord<K, V>) -> Result<(), SendError> {
+        self.enqueue(message, None)
+    }
+
+    /// Enqueues the records for sending and returns the result of each, in order.
+    ///
+    /// The [`QueueFullPolicy`] applies to each record, but no record waits for room in the queue
+    /// beyond `deadline`: once it has passed, the remaining records fail with `QueueFull` unless
+    /// there is room at once.
+    pub fn send_batch<'a, K, V, I>(&self, records: I, deadline: Option<Instant>) -> Vec<Result<(), SendError>>
+    where
+        K: Serializer + 'a,
+        V: Serializer + 'a,
+        I: IntoIterator<Item = &'a ProducerRecord<K, V>>,
+    {
+        records.into_iter().map(|record| self.enqueue(record, deadline)).collect()
     }
 
-    #[allow(clippy::result_large_err)] // TODO(design): optimize return value / API
-    pub fn send<'a>(
-        &'a self,
-        message: &'a ProducerRecord,
-    ) -> Result<(), (KafkaError, BaseRecord<'_, Vec<u8>, [u8]>)> {
-        let mut headers = OwnedHeaders::new_with_capacity(message.headers.len());
-        for (key, value) in &message.headers {
+    /// Enqueues the record, waiting for room in the queue as the policy allows but not beyond
+    /// `deadline`.
+    fn enqueue<K: Serializer, V: Serializer>(
+        &self,
+        message: &ProducerRecord<K, V>,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendError> {
+        let key = message.key.serialize().map_err(SendError::Serialization)?;
+        let payload = message.payload.serialize().map_err(SendError::Serialization)?;
+
+        let span = tracing::info_span!(
+            "kafka.send",
+            messaging.system = "kafka",
+            messaging.destination.name = %message.topic,
+        );
+        trace::set_remote_parent(&span, &trace::HeaderMapExtractor(&message.headers));
+
+        let mut trace_headers = HashMap::new();
+        trace::inject(&span, &mut trace_headers);
+
+        let mut headers = OwnedHeaders::new_with_capacity(message.headers.len() + trace_headers.len());
+        for (key, value) in message.headers.iter().filter(|(key, _)| !trace_headers.contains_key(*key)) {
+            let header = Header{key: key.as_str(), value: Some(value)};
+            headers = headers.insert(header);
+        }
+        for (key, value) in &trace_headers {
             let header = Header{key: key.as_str(), value: Some(value)};
             headers = headers.insert(header);
         }
 
-        return self.producer.send(
-            BaseRecord::to(&message.topic)
-                .key(&message.key)
-                .payload(message.payload.as_slice())
-                .headers(headers)
-        );
+        let _entered = span.enter();
+        let mut record = BaseRecord::with_opaque_to(&message.topic, Box::new(Instant::now()))
+            .key(key.as_ref())
+            .payload(payload.as_ref())
+            .headers(headers);
+
+        let started = Instant::now();
+        let mut attempt = 0;
+        let err = loop {
+            match self.producer.send(record) {
+                Ok(()) => return Ok(()),
+                Err((err @ KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    self.metrics.queue_full.fetch_add(1, Ordering::Relaxed);
+                    let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                    let wait = self.queue_full_policy.wait(attempt, started.elapsed());
+                    let Some(wait) = wait.filter(|_| left != Some(Duration::ZERO)) else {
+                        break err;
+                    };
+                    let wait = left.map_or(wait, |left| wait.min(left));
+
+                    match self.queue_full_policy {
+                        QueueFullPolicy::Block { .. } => self.producer.poll(wait),
+                        _ => std::thread::sleep(wait),
+                    }
+                    // The latency starts when the record is enqueued, not with the waiting
+                    record = returned;
+                    *record.delivery_opaque = Instant::now();
+                    attempt += 1;
+                }
+                Err((err, _)) => break err,
+            }
+        };
+
+        self.metrics.record_error(&err);
+        Err(SendError::Kafka(err))
     }
 
     pub fn metrics(&self) -> Arc<Metrics> {
         Arc::clone(&self.metrics)
     }
+
+    /// Copies the metrics, with the current number of messages waiting to be delivered.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            queue_depth: self.producer.in_flight_count().max(0) as u64,
+            ..self.metrics.snapshot()
+        }
+    }
+}
+
+/// Error of the transactions of [`KafkaProducer`].
+#[derive(Debug)]
+pub enum TransactionError {
+    /// The producer has no transactional ID.
+    NotTransactional,
+    /// Another instance with the same transactional ID has taken over, the producer must be
+    /// dropped.
+    Fenced(KafkaError),
+    /// The transaction must be aborted, then it can be retried.
+    Abortable(KafkaError),
+    /// The operation can be retried.
+    Retriable(KafkaError),
+    /// The producer is no longer usable.
+    Fatal(KafkaError),
+    /// Any other error, e.g., the state of the transaction does not allow the operation.
+    Other(KafkaError),
+}
+
+impl From<KafkaError> for TransactionError {
+    fn from(err: KafkaError) -> Self {
+        let KafkaError::Transaction(txn_err) = &err else {
+            return TransactionError::Other(err);
+        };
+
+        let fenced = matches!(
+            txn_err.code(),
+            RDKafkaErrorCode::Fenced
+                | RDKafkaErrorCode::ProducerFenced
+                | RDKafkaErrorCode::InvalidProducerEpoch
+                | RDKafkaErrorCode::TransactionCoordinatorFenced
+        );
+
+        if fenced {
+            TransactionError::Fenced(err)
+        } else if txn_err.is_fatal() {
+            TransactionError::Fatal(err)
+        } else if txn_err.txn_requires_abort() {
+            TransactionError::Abortable(err)
+        } else if txn_err.is_retriable() {
+            TransactionError::Retriable(err)
+        } else {
+            TransactionError::Other(err)
+        }
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::NotTransactional => write!(f, "Producer is not transactional"),
+            TransactionError::Fenced(err) => write!(f, "Producer is fenced: {}", err),
+            TransactionError::Abortable(err) => write!(f, "Transaction must be aborted: {}", err),
+            TransactionError::Retriable(err) => write!(f, "Retriable transaction error: {}", err),
+            TransactionError::Fatal(err) => write!(f, "Fatal transaction error: {}", err),
+            TransactionError::Other(err) => write!(f, "Transaction error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+/**
+ * An ongoing transaction of [`KafkaProducer`]
+ *
+ * It is aborted when dropped without committing, e.g., when committing fails.
+ *
+ * Example of consume-transform-produce:
+ * ```rust,no_run
+ *   # use std::time::Duration;
+ *   # fn run(producer: &ers::kafka::KafkaProducer, record: &ers::kafka::ProducerRecord,
+ *   #        offsets: &rdkafka::TopicPartitionList, group: &rdkafka::consumer::ConsumerGroupMetadata)
+ *   #     -> Result<(), Box<dyn std::error::Error>> {
+ *   let txn = producer.begin_transaction()?;
+ *   txn.send(record)?;
+ *   txn.send_offsets(offsets, group, Duration::from_secs(10))?;
+ *   txn.commit(Duration::from_secs(10))?;
+ *   # Ok(())
+ *   # }
+ * ```
+ */
+pub struct Transaction<'a> {
+    producer: &'a KafkaProducer,
+    finished: bool,
+}
+
+impl Transaction<'_> {
+    /// Enqueues the record as part of the transaction, see [`KafkaProducer::send`].
+    pub fn send<K: Serializer, V: Serializer>(&self, message: &ProducerRecord<K, V>) -> Result<(), SendError> {
+        self.producer.send(message)
+    }
+
+    /// Commits the consumed offsets as part of the transaction.
+    pub fn send_offsets(
+        &self,
+        offsets: &TopicPartitionList,
+        group: &ConsumerGroupMetadata,
+        timeout: Duration,
+    ) -> Result<(), TransactionError> {
+        Ok(self.producer.producer.send_offsets_to_transaction(offsets, group, timeout)?)
+    }
+
+    /// Flushes the records and commits the transaction.
+    pub fn commit(mut self, timeout: Duration) -> Result<(), TransactionError> {
+        self.producer.producer.commit_transaction(timeout)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Aborts the transaction, the records sent in it are discarded.
+    pub fn abort(mut self, timeout: Duration) -> Result<(), TransactionError> {
+        self.finished = true;
+        Ok(self.producer.producer.abort_transaction(timeout)?)
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(err) = self.producer.producer.abort_transaction(TRANSACTION_TIMEOUT) {
+                log::warn!("Failed to abort transaction: {}", err);
+            }
+        }
+    }
+}
+
+/// [`Extractor`] for the headers of a consumed message.
+pub struct ConsumerHeaders<'a, H: Headers>(pub &'a H);
+
+impl<H: Headers> Extractor for ConsumerHeaders<'_, H> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|header| header.key == key)
+            .and_then(|header| header.value)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|header| header.key).collect()
+    }
+}
+
+/// The trace context of a consumed message, see [`crate::trace`].
+pub fn trace_context<H: Headers>(headers: &H) -> Context {
+    trace::extract_context(&ConsumerHeaders(headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use rdkafka::mocking::MockCluster;
+
+    use super::*;
+
+    fn record(topic: &str) -> ProducerRecord {
+        ProducerRecord {
+            key: "test".into(),
+            topic: topic.to_string(),
+            headers: HashMap::new(),
+            payload: b"payload".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_not_transactional() {
+        let producer = KafkaProducerBuilder::new()
+            .brokers(["localhost:9092"])
+            .enable_idempotence(true)
+            .build()
+            .unwrap();
+        assert!(matches!(producer.begin_transaction(), Err(TransactionError::NotTransactional)));
+    }
+
+    #[test]
+    fn test_typed_options() {
+        let builder = KafkaProducerBuilder::new()
+            .brokers(["kafka-0:9092", "kafka-1:9092"])
+            .acks(Acks::All)
+            .linger(Duration::from_millis(5))
+            .compression(Compression::Lz4)
+            .security_protocol(SecurityProtocol::SaslPlaintext)
+            .sasl(SaslMechanism::Plain, "user", "secret");
+
+        assert_eq!(builder.get("bootstrap.servers"), Some("kafka-0:9092,kafka-1:9092"));
+        assert_eq!(builder.get("linger.ms"), Some("5"));
+        assert_eq!(builder.get("sasl.mechanism"), Some("PLAIN"));
+        assert!(builder.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_config_reported() {
+        let result = KafkaProducerBuilder::new()
+            .enable_idempotence(true)
+            .acks(Acks::Leader)
+            .property("linger.mss", "5")
+            .property("compression.type", "brotli")
+            .build();
+
+        let Err(ProducerConfigError::Invalid(errors)) = result else {
+            panic!("Invalid configuration accepted");
+        };
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[0].starts_with("bootstrap.servers"));
+        assert!(errors[1].starts_with("acks"));
+        assert!(errors[2].starts_with("compression.type"));
+        assert!(errors[3].starts_with("linger.mss"));
+    }
+
+    #[test]
+    fn test_transaction() {
+        let cluster = MockCluster::new(3).unwrap();
+        cluster.create_topic("output_1", 1, 3).unwrap();
+
+        let producer = KafkaProducerBuilder::new()
+            .brokers([cluster.bootstrap_servers()])
+            .transactional_id("test")
+            .build()
+            .unwrap();
+
+        let txn = producer.begin_transaction().unwrap();
+        assert!(matches!(producer.begin_transaction(), Err(TransactionError::Other(_))));
+        txn.send(&record("output_1")).unwrap();
+        txn.commit(Duration::from_secs(10)).unwrap();
+        assert_eq!(producer.metrics().sent_messages.load(Ordering::Relaxed), 1);
+
+        let txn = producer.begin_transaction().unwrap();
+        txn.send(&record("output_1")).unwrap();
+        drop(txn);
+        assert!(producer.begin_transaction().is_ok());
+    }
+
+    #[test]
+    fn test_partition_metrics() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("output_1", 2, 1).unwrap();
+
+        let offsets = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reported = Arc::clone(&offsets);
+        let producer = KafkaProducerBuilder::new()
+            .brokers([cluster.bootstrap_servers()])
+            .statistics_interval(Duration::from_millis(100))
+            .on_delivery(move |report| reported.lock().unwrap().push(*report.result.as_ref().unwrap()))
+            .build()
+            .unwrap();
+
+        for _ in 0..3 {
+            producer.send(&record("output_1")).unwrap();
+        }
+        producer.flush(Duration::from_secs(10)).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+
+        let snapshot = producer.snapshot();
+        assert_eq!(snapshot.sent_messages, 3);
+        assert_eq!(snapshot.queue_depth, 0);
+        assert_eq!(snapshot.partitions.len(), 1);
+        assert_eq!(snapshot.partitions[0].topic, "output_1");
+        assert_eq!(snapshot.partitions[0].sent_bytes, 3 * 7);
+        assert_eq!(snapshot.partitions[0].latency.count, 3);
+        assert!(snapshot.statistics.is_some_and(|x| x.topics.contains_key("output_1")));
+        assert_eq!(*offsets.lock().unwrap(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_queue_full_policy() {
+        let millis = Duration::from_millis;
+        let retry = QueueFullPolicy::Retry { retries: 3, initial_backoff: millis(10), max_backoff: millis(25) };
+        assert_eq!(retry.wait(0, Duration::ZERO), Some(millis(10)));
+        assert_eq!(retry.wait(1, Duration::ZERO), Some(millis(20)));
+        assert_eq!(retry.wait(2, Duration::ZERO), Some(millis(25)));
+        assert_eq!(retry.wait(3, Duration::ZERO), None);
+
+        let block = QueueFullPolicy::Block { timeout: millis(100) };
+        assert_eq!(block.wait(0, Duration::ZERO), Some(QUEUE_FULL_POLL));
+        assert_eq!(block.wait(9, millis(95)), Some(millis(5)));
+        assert_eq!(block.wait(10, millis(100)), None);
+        assert_eq!(QueueFullPolicy::FailFast.wait(0, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_send_batch_queue_full() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("output_1", 1, 1).unwrap();
+
+        let builder = || {
+            KafkaProducerBuilder::new()
+                .brokers([cluster.bootstrap_servers()])
+                .property("queue.buffering.max.messages", "1")
+        };
+        let records = (0..5).map(|_| record("output_1")).collect::<Vec<_>>();
+
+        let producer = builder().build().unwrap();
+        let results = producer.send_batch(&records, None);
+        assert!(results[0].is_ok());
+        assert!(results[1..].iter().any(|x| {
+            x.as_ref().is_err_and(|err| err.kafka_error().and_then(|x| x.rdkafka_error_code()) == Some(RDKafkaErrorCode::QueueFull))
+        }));
+        assert_eq!(producer.snapshot().errors["QueueFull"], producer.metrics().queue_full.load(Ordering::Relaxed));
+
+        let producer = builder()
+            .queue_full_policy(QueueFullPolicy::Block { timeout: Duration::from_secs(10) })
+            .build()
+            .unwrap();
+        assert!(producer.send_batch(&records, None).iter().all(Result::is_ok));
+        assert!(producer.metrics().queue_full.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn test_send_batch_deadline() {
+        // Nothing is delivered without a broker, so the queue stays full.
+        let producer = KafkaProducerBuilder::new()
+            .brokers(["localhost:1"])
+            .property("queue.buffering.max.messages", "1")
+            .queue_full_policy(QueueFullPolicy::Block { timeout: Duration::from_secs(10) })
+            .build()
+            .unwrap();
+        let records = (0..5).map(|_| record("output_1")).collect::<Vec<_>>();
+
+        let started = Instant::now();
+        let results = producer.send_batch(&records, Some(started + Duration::from_millis(200)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(results[0].is_ok());
+        assert!(results[1..].iter().all(|x| {
+            x.as_ref().is_err_and(|err| err.kafka_error().and_then(|x| x.rdkafka_error_code()) == Some(RDKafkaErrorCode::QueueFull))
+        }));
+    }
 }
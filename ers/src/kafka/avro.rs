@@ -0,0 +1,531 @@
+//! Avro binary encoding of JSON values, for the payloads of [`Schema::avro`](super::Schema::avro).
+//!
+//! [`AvroSchema`] parses the schema document as it is registered, and [`Avro`] serializes any
+//! `serde` value with it, e.g., inside [`Registered`](super::Registered) to produce it in the
+//! schema registry wire format. Logical types are encoded as their underlying type.
+//!
+//! The JSON values map to Avro as in the Avro JSON encoding, except for the unions:
+//! * `null`, booleans, numbers and strings as they are, integers must fit `int` or `long`
+//! * `bytes` and `fixed` from strings of the characters U+0000 to U+00FF, one per byte
+//! * records and maps from objects, the missing fields of a record take their default
+//! * enums from their symbols
+//! * a union takes the first of its types the value conforms to, it is not wrapped in an object
+//!
+//! The decoder checks every count and length against the rest of the input, so that hostile
+//! input cannot exhaust the memory or the stack, see [`AvroSchema::decode`].
+//!
+//! ## Example
+//!
+//! ```
+//! use ers::kafka::{Avro, AvroSchema, Serializer};
+//!
+//! let schema = AvroSchema::parse(r#"{"type": "record", "name": "Event", "fields": [
+//!     {"name": "id", "type": "long"},
+//!     {"name": "source", "type": ["null", "string"], "default": null}
+//! ]}"#).unwrap();
+//!
+//! let event = Avro { schema: &schema, value: serde_json::json!({"id": 7}) };
+//! let bytes = event.serialize().unwrap();
+//! assert_eq!(&bytes[..], &[14, 0]);
+//! assert_eq!(schema.decode(&bytes).unwrap(), serde_json::json!({"id": 7, "source": null}));
+//! ```
+
+use std::{borrow::Cow, collections::HashMap};
+
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+
+use super::serialization::{SerializationError, Serializer};
+use crate::Varint;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<Field>),
+    Enum(Vec<String>),
+    Array(Box<Type>),
+    Map(Box<Type>),
+    Union(Vec<Type>),
+    Fixed(usize),
+    /// A named type by its full name, defined in `AvroSchema::named`
+    Named(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    name: String,
+    schema: Type,
+    default: Option<Value>,
+}
+
+/// A parsed Avro schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvroSchema {
+    root: Type,
+    /// The records, enums and fixed types by their full name
+    named: HashMap<String, Type>,
+}
+
+fn avro_error(message: impl Into<String>) -> SerializationError {
+    SerializationError::Avro(message.into())
+}
+
+/// The full name of a named type, `namespace.name`.
+fn full_name(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) if !name.contains('.') && !namespace.is_empty() => format!("{}.{}", namespace, name),
+        _ => name.to_string(),
+    }
+}
+
+impl AvroSchema {
+    pub fn parse(definition: &str) -> Result<Self, SerializationError> {
+        let definition = serde_json::from_str::<Value>(definition).map_err(SerializationError::Json)?;
+        let mut named = HashMap::new();
+        let root = Self::parse_type(&definition, None, &mut named)?;
+        Ok(Self { root, named })
+    }
+
+    fn parse_type(
+        definition: &Value,
+        namespace: Option<&str>,
+        named: &mut HashMap<String, Type>,
+    ) -> Result<Type, SerializationError> {
+        match definition {
+            Value::String(name) => return Self::parse_name(name, namespace, named),
+            Value::Array(types) => {
+                let types = types.iter().map(|x| Self::parse_type(x, namespace, named));
+                return types.collect::<Result<_, _>>().map(Type::Union);
+            }
+            Value::Object(_) => {}
+            _ => return Err(avro_error(format!("Invalid schema: {}", definition))),
+        }
+
+        let kind = match &definition["type"] {
+            Value::String(kind) => kind.as_str(),
+            nested => return Self::parse_type(nested, namespace, named),
+        };
+        let name = || {
+            let name = definition["name"].as_str().ok_or_else(|| avro_error(format!("{} without name", kind)))?;
+            let namespace = definition["namespace"].as_str().or(namespace);
+            Ok::<_, SerializationError>(full_name(name, namespace))
+        };
+
+        match kind {
+            "record" | "error" => {
+                let name = name()?;
+                let namespace = name.rsplit_once('.').map(|(namespace, _)| namespace.to_string());
+                // Defined before the fields, which may refer to it
+                named.insert(name.clone(), Type::Record(Vec::new()));
+
+                let fields = definition["fields"].as_array().ok_or_else(|| avro_error("Record without fields"))?;
+                let fields = fields
+                    .iter()
+                    .map(|field| {
+                        Ok(Field {
+                            name: field["name"].as_str().ok_or_else(|| avro_error("Field without name"))?.to_string(),
+                            schema: Self::parse_type(&field["type"], namespace.as_deref(), named)?,
+                            default: field.get("default").cloned(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, SerializationError>>()?;
+
+                named.insert(name, Type::Record(fields.clone()));
+                Ok(Type::Record(fields))
+            }
+            "enum" => {
+                let symbols = definition["symbols"]
+                    .as_array()
+                    .and_then(|x| x.iter().map(|x| x.as_str().map(str::to_string)).collect::<Option<Vec<_>>>())
+                    .ok_or_else(|| avro_error("Enum without symbols"))?;
+                named.insert(name()?, Type::Enum(symbols.clone()));
+                Ok(Type::Enum(symbols))
+            }
+            "fixed" => {
+                let size = definition["size"].as_u64().ok_or_else(|| avro_error("Fixed without size"))? as usize;
+                named.insert(name()?, Type::Fixed(size));
+                Ok(Type::Fixed(size))
+            }
+            "array" => Ok(Type::Array(Box::new(Self::parse_type(&definition["items"], namespace, named)?))),
+            "map" => Ok(Type::Map(Box::new(Self::parse_type(&definition["values"], namespace, named)?))),
+            primitive => Self::parse_name(primitive, namespace, named),
+        }
+    }
+
+    fn parse_name(name: &str, namespace: Option<&str>, named: &HashMap<String, Type>) -> Result<Type, SerializationError> {
+        Ok(match name {
+            "null" => Type::Null,
+            "boolean" => Type::Boolean,
+            "int" => Type::Int,
+            "long" => Type::Long,
+            "float" => Type::Float,
+            "double" => Type::Double,
+            "bytes" => Type::Bytes,
+            "string" => Type::String,
+            name => {
+                let name = [full_name(name, namespace), name.to_string()]
+                    .into_iter()
+                    .find(|x| named.contains_key(x))
+                    .ok_or_else(|| avro_error(format!("Unknown type: {}", name)))?;
+                Type::Named(name)
+            }
+        })
+    }
+
+    /// Encodes the value in the Avro binary encoding.
+    pub fn encode(&self, value: &Value) -> Result<Vec<u8>, SerializationError> {
+        let mut data = Vec::new();
+        self.encode_type(&self.root, value, &mut data).map_err(avro_error)?;
+        Ok(data)
+    }
+
+    fn encode_type(&self, schema: &Type, value: &Value, data: &mut Vec<u8>) -> Result<(), String> {
+        let mismatch = || format!("{} is not {:?}", value, schema);
+
+        match schema {
+            Type::Null => value.is_null().then_some(()).ok_or_else(mismatch)?,
+            Type::Boolean => data.push(value.as_bool().ok_or_else(mismatch)? as u8),
+            Type::Int => {
+                let value = value.as_i64().filter(|x| i32::try_from(*x).is_ok()).ok_or_else(mismatch)?;
+                write_long(data, value);
+            }
+            Type::Long => write_long(data, value.as_i64().ok_or_else(mismatch)?),
+            Type::Float => data.extend_from_slice(&(value.as_f64().ok_or_else(mismatch)? as f32).to_le_bytes()),
+            Type::Double => data.extend_from_slice(&value.as_f64().ok_or_else(mismatch)?.to_le_bytes()),
+            Type::Bytes => {
+                let bytes = to_bytes(value).ok_or_else(mismatch)?;
+                write_long(data, bytes.len() as i64);
+                data.extend_from_slice(&bytes);
+            }
+            Type::String => {
+                let value = value.as_str().ok_or_else(mismatch)?;
+                write_long(data, value.len() as i64);
+                data.extend_from_slice(value.as_bytes());
+            }
+            Type::Record(fields) => {
+                let object = value.as_object().ok_or_else(mismatch)?;
+                for field in fields {
+                    let value = object
+                        .get(&field.name)
+                        .or(field.default.as_ref())
+                        .ok_or_else(|| format!("Missing field `{}`", field.name))?;
+                    self.encode_type(&field.schema, value, data)?;
+                }
+            }
+            Type::Enum(symbols) => {
+                let index = symbols.iter().position(|x| Some(x.as_str()) == value.as_str()).ok_or_else(mismatch)?;
+                write_long(data, index as i64);
+            }
+            Type::Array(items) => {
+                let values = value.as_array().ok_or_else(mismatch)?;
+                if !values.is_empty() {
+                    write_long(data, values.len() as i64);
+                    for value in values {
+                        self.encode_type(items, value, data)?;
+                    }
+                }
+                write_long(data, 0);
+            }
+            Type::Map(values) => {
+                let object = value.as_object().ok_or_else(mismatch)?;
+                if !object.is_empty() {
+                    write_long(data, object.len() as i64);
+                    for (key, value) in object {
+                        write_long(data, key.len() as i64);
+                        data.extend_from_slice(key.as_bytes());
+                        self.encode_type(values, value, data)?;
+                    }
+                }
+                write_long(data, 0);
+            }
+            Type::Union(types) => {
+                let (index, encoded) = types
+                    .iter()
+                    .enumerate()
+                    .find_map(|(index, schema)| {
+                        let mut encoded = Vec::new();
+                        self.encode_type(schema, value, &mut encoded).ok().map(|_| (index, encoded))
+                    })
+                    .ok_or_else(mismatch)?;
+                write_long(data, index as i64);
+                data.extend_from_slice(&encoded);
+            }
+            Type::Fixed(size) => data.extend_from_slice(&to_bytes(value).filter(|x| x.len() == *size).ok_or_else(mismatch)?),
+            Type::Named(name) => self.encode_type(&self.named[name], value, data)?,
+        }
+        Ok(())
+    }
+
+    /// Decodes a value of the Avro binary encoding, the inverse of [`AvroSchema::encode`].
+    ///
+    /// The input may be hostile: the arrays and maps may not hold more items in total than the
+    /// input has bytes, and the values may be nested at most [`MAX_DEPTH`] levels deep.
+    pub fn decode(&self, data: &[u8]) -> Result<Value, SerializationError> {
+        let mut input = Input { data, items: data.len(), depth: 0 };
+        let value = self.decode_type(&self.root, &mut input).map_err(avro_error)?;
+        if !input.data.is_empty() {
+            return Err(avro_error(format!("{} bytes left after the value", input.data.len())));
+        }
+        Ok(value)
+    }
+
+    fn decode_type(&self, schema: &Type, input: &mut Input) -> Result<Value, String> {
+        if input.depth >= MAX_DEPTH {
+            return Err(format!("Nested deeper than {} levels", MAX_DEPTH));
+        }
+        input.depth += 1;
+        let value = self.decode_value(schema, input);
+        input.depth -= 1;
+        value
+    }
+
+    fn decode_value(&self, schema: &Type, input: &mut Input) -> Result<Value, String> {
+        Ok(match schema {
+            Type::Null => Value::Null,
+            Type::Boolean => Value::Bool(input.take(1)?[0] != 0),
+            Type::Int => Value::from(i32::try_from(input.long()?).map_err(|_| "Int out of range")?),
+            Type::Long => Value::from(input.long()?),
+            Type::Float => {
+                let value = f32::from_le_bytes(input.take(4)?.try_into().unwrap());
+                Number::from_f64(value as f64).map_or(Value::Null, Value::Number)
+            }
+            Type::Double => {
+                let value = f64::from_le_bytes(input.take(8)?.try_into().unwrap());
+                Number::from_f64(value).map_or(Value::Null, Value::Number)
+            }
+            Type::Bytes => {
+                let length = input.length()?;
+                Value::String(input.take(length)?.iter().map(|x| *x as char).collect())
+            }
+            Type::String => Value::String(input.string()?.to_string()),
+            Type::Record(fields) => {
+                let mut object = Map::new();
+                for field in fields {
+                    object.insert(field.name.clone(), self.decode_type(&field.schema, input)?);
+                }
+                Value::Object(object)
+            }
+            Type::Enum(symbols) => {
+                let index = input.length()?;
+                Value::String(symbols.get(index).ok_or("Invalid enum index")?.clone())
+            }
+            Type::Array(items) => {
+                let mut values = Vec::new();
+                while let Some(count) = input.block()? {
+                    for _ in 0..count {
+                        values.push(self.decode_type(items, input)?);
+                    }
+                }
+                Value::Array(values)
+            }
+            Type::Map(values) => {
+                let mut object = Map::new();
+                while let Some(count) = input.block()? {
+                    for _ in 0..count {
+                        let key = input.string()?.to_string();
+                        object.insert(key, self.decode_type(values, input)?);
+                    }
+                }
+                Value::Object(object)
+            }
+            Type::Union(types) => {
+                let index = input.length()?;
+                self.decode_type(types.get(index).ok_or("Invalid union index")?, input)?
+            }
+            Type::Fixed(size) => Value::String(input.take(*size)?.iter().map(|x| *x as char).collect()),
+            Type::Named(name) => self.decode_type(&self.named[name], input)?,
+        })
+    }
+}
+
+/// How deep the decoded values may be nested, like the JSON parser of `serde_json`.
+pub const MAX_DEPTH: usize = 128;
+
+/// The longest encoding of a 64-bit varint.
+const MAX_LONG_LENGTH: usize = 10;
+
+/// The bytes left to decode, with the limits of [`AvroSchema::decode`].
+struct Input<'a> {
+    data: &'a [u8],
+    /// The number of array and map items which may still be decoded
+    items: usize,
+    depth: usize,
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let (value, rest) = self.data.split_at_checked(length).ok_or("Insufficient data")?;
+        self.data = rest;
+        Ok(value)
+    }
+
+    /// Reads a zig-zag encoded variable-length integer.
+    fn long(&mut self) -> Result<i64, String> {
+        let varint = Varint::try_from(self.data)?;
+        if varint.length() as usize > MAX_LONG_LENGTH {
+            return Err("Long too long".to_string());
+        }
+        let zigzag = u64::try_from(varint.value()).map_err(|_| "Long out of range")?;
+        self.data = &self.data[varint.length() as usize..];
+        Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64))
+    }
+
+    fn length(&mut self) -> Result<usize, String> {
+        usize::try_from(self.long()?).map_err(|_| "Negative length".to_string())
+    }
+
+    fn string(&mut self) -> Result<&'a str, String> {
+        let length = self.length()?;
+        std::str::from_utf8(self.take(length)?).map_err(|err| err.to_string())
+    }
+
+    /// The number of items of the next block of an array or a map, `None` at the end.
+    fn block(&mut self) -> Result<Option<usize>, String> {
+        let count = match self.long()? {
+            0 => return Ok(None),
+            // A negative count is followed by the size of the block in bytes
+            count if count < 0 => {
+                self.long()?;
+                count.unsigned_abs()
+            }
+            count => count as u64,
+        };
+
+        match usize::try_from(count).ok().filter(|count| *count <= self.items) {
+            Some(count) => {
+                self.items -= count;
+                Ok(Some(count))
+            }
+            None => Err(format!("Block of {} items is longer than the input", count)),
+        }
+    }
+}
+
+/// The bytes of a string of the characters U+0000 to U+00FF.
+fn to_bytes(value: &Value) -> Option<Vec<u8>> {
+    value.as_str()?.chars().map(|x| u8::try_from(x).ok()).collect()
+}
+
+/// Writes a zig-zag encoded variable-length integer.
+fn write_long(data: &mut Vec<u8>, value: i64) {
+    let zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    data.extend(Varint::from(zigzag).to_bytes());
+}
+
+/// A value serialized in the Avro binary encoding of the schema.
+#[derive(Debug, Clone)]
+pub struct Avro<'a, T> {
+    pub schema: &'a AvroSchema,
+    pub value: T,
+}
+
+impl<T: Serialize> Serializer for Avro<'_, T> {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError> {
+        let value = serde_json::to_value(&self.value).map_err(SerializationError::Json)?;
+        self.schema.encode(&value).map(Cow::Owned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_primitives() {
+        let encode = |schema: &str, value: Value| AvroSchema::parse(schema).unwrap().encode(&value).unwrap();
+
+        assert_eq!(encode(r#""long""#, json!(1)), [0x02]);
+        assert_eq!(encode(r#""long""#, json!(-64)), [0x7f]);
+        assert_eq!(encode(r#""long""#, json!(64)), [0x80, 0x01]);
+        assert_eq!(encode(r#""string""#, json!("foo")), [0x06, b'f', b'o', b'o']);
+        assert_eq!(encode(r#"{"type": "int", "logicalType": "date"}"#, json!(1)), [0x02]);
+        assert_eq!(encode(r#""double""#, json!(1.5)), 1.5f64.to_le_bytes());
+        assert_eq!(encode(r#"["null", "string"]"#, json!(null)), [0x00]);
+        assert_eq!(encode(r#"["null", "string"]"#, json!("a")), [0x02, 0x02, b'a']);
+        assert_eq!(encode(r#"{"type": "array", "items": "long"}"#, json!([3, 27])), [0x04, 0x06, 0x36, 0x00]);
+
+        let schema = AvroSchema::parse(r#""int""#).unwrap();
+        assert!(matches!(schema.encode(&json!(1u64 << 40)), Err(SerializationError::Avro(_))));
+        assert!(matches!(schema.encode(&json!("1")), Err(SerializationError::Avro(_))));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let schema = AvroSchema::parse(
+            r#"{"type": "record", "name": "Event", "namespace": "org.onap.ves", "fields": [
+                {"name": "domain", "type": {"type": "enum", "name": "Domain", "symbols": ["fault", "heartbeat"]}},
+                {"name": "sequence", "type": "long"},
+                {"name": "id", "type": {"type": "fixed", "name": "Id", "size": 2}},
+                {"name": "payload", "type": "bytes"},
+                {"name": "fields", "type": {"type": "map", "values": "string"}},
+                {"name": "ratio", "type": "float", "default": 0.5},
+                {"name": "next", "type": ["null", "org.onap.ves.Event"], "default": null},
+                {"name": "other", "type": ["null", "Domain"]}
+            ]}"#,
+        )
+        .unwrap();
+        let event = json!({
+            "domain": "heartbeat",
+            "sequence": -3,
+            "id": "\u{00ff}a",
+            "payload": "\u{0000}\u{0001}",
+            "fields": { "a": "b" },
+            "next": {
+                "domain": "fault", "sequence": 1, "id": "xy", "payload": "", "fields": {}, "other": "fault",
+            },
+            "other": null,
+        });
+
+        let data = schema.encode(&event).unwrap();
+        let mut expected = event.clone();
+        expected["ratio"] = json!(0.5);
+        expected["next"]["ratio"] = json!(0.5);
+        expected["next"]["next"] = json!(null);
+        assert_eq!(schema.decode(&data).unwrap(), expected);
+
+        assert!(schema.decode(&data[..data.len() - 1]).is_err());
+        assert!(schema.encode(&json!({ "domain": "fault" })).is_err());
+    }
+
+    #[test]
+    fn test_hostile_input() {
+        let nulls = AvroSchema::parse(r#"{"type": "array", "items": "null"}"#).unwrap();
+        assert_eq!(nulls.decode(&[0x04, 0x00]).unwrap(), json!([null, null]));
+        // i64::MAX nulls in two blocks of no bytes at all
+        assert!(nulls.decode(&[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00]).is_err());
+        assert_eq!(nulls.decode(&[0x01, 0x00, 0x00]).unwrap(), json!([null]));
+        assert!(nulls.decode(&[0x01]).is_err());
+
+        let long = AvroSchema::parse(r#""long""#).unwrap();
+        assert_eq!(long.decode(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).unwrap(), json!(i64::MIN));
+        // Wider than 64 bits
+        assert!(long.decode(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x03]).is_err());
+        assert!(long.decode(&[0xff; 11]).is_err());
+        assert!(AvroSchema::parse(r#""int""#).unwrap().decode(&[0x80, 0x80, 0x80, 0x80, 0x10]).is_err());
+
+        let strings = AvroSchema::parse(r#"{"type": "map", "values": "string"}"#).unwrap();
+        assert!(strings.decode(&[0x02, 0x02, b'a', 0x7e, b'b', 0x00]).is_err());
+        assert!(strings.decode(&[0x02, 0x02, b'a']).is_err());
+        assert!(strings.decode(&[]).is_err());
+
+        let list = AvroSchema::parse(r#"{"type": "record", "name": "List", "fields": [{"name": "next", "type": ["null", "List"]}]}"#).unwrap();
+        assert_eq!(list.decode(&[0x02, 0x00]).unwrap(), json!({"next": {"next": null}}));
+        assert!(list.decode(&[0x02; 1000]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        assert!(matches!(AvroSchema::parse(r#""Unknown""#), Err(SerializationError::Avro(_))));
+        assert!(matches!(AvroSchema::parse(r#"{"type": "record", "name": "A"}"#), Err(SerializationError::Avro(_))));
+        assert!(matches!(AvroSchema::parse("{"), Err(SerializationError::Json(_))));
+    }
+}
@@ -0,0 +1,300 @@
+//! Counters of the outgoing data of [`KafkaProducer`](super::KafkaProducer).
+//!
+//! Besides the global counters of [`Metrics`], the deliveries are counted per topic and
+//! partition, with a histogram of the delivery latency from enqueueing to the acknowledgement of
+//! the broker. The errors are counted by their code, and the latest librdkafka statistics are
+//! kept if `statistics.interval.ms` is set, see
+//! [`KafkaProducerBuilder::statistics_interval`](super::KafkaProducerBuilder::statistics_interval).
+//!
+//! [`Metrics::snapshot`] copies all of them into a [`MetricsSnapshot`], which can be serialized.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use rdkafka::{error::KafkaError, statistics::Statistics};
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds of the latency histogram buckets in milliseconds, the last bucket is unbounded
+pub const LATENCY_BOUNDS_MS: [u64; 12] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 5000, 30000];
+
+/**
+ * Thread-safe counters for outgoing data
+ */
+#[derive(Default, Debug)]
+pub struct Metrics {
+    /// The number of successfully sent messages
+    pub sent_messages: AtomicU64,
+    /// The number of successfully sent bytes (only the payload)
+    pub sent_bytes: AtomicU64,
+    /// The number of messages failed to send
+    pub dropped_messages: AtomicU64,
+    /// The number of bytes failed to send (only the payload)
+    pub dropped_bytes: AtomicU64,
+    /// The number of times a record found the local queue full, including the retries
+    pub queue_full: AtomicU64,
+    partitions: Mutex<HashMap<(String, i32), PartitionMetrics>>,
+    errors: Mutex<HashMap<String, u64>>,
+    statistics: Mutex<Option<Statistics>>,
+}
+
+#[derive(Default, Debug)]
+struct PartitionMetrics {
+    sent_messages: u64,
+    sent_bytes: u64,
+    dropped_messages: u64,
+    dropped_bytes: u64,
+    latency: LatencyHistogram,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyHistogram {
+    /// The number of deliveries of each bucket of [`LATENCY_BOUNDS_MS`], and above the last bound
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    /// Sum of the latencies in microseconds
+    pub sum_us: u64,
+    /// The highest latency in microseconds
+    #[serde(default)]
+    pub max_us: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self { buckets: vec![0; LATENCY_BOUNDS_MS.len() + 1], count: 0, sum_us: 0, max_us: 0 }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let ms = latency.as_millis();
+        let bucket = LATENCY_BOUNDS_MS.iter().position(|bound| ms <= *bound as u128);
+        self.buckets[bucket.unwrap_or(LATENCY_BOUNDS_MS.len())] += 1;
+        self.count += 1;
+        self.sum_us += latency.as_micros() as u64;
+        self.max_us = self.max_us.max(latency.as_micros() as u64);
+    }
+
+    /// Adds the latencies recorded by `other`.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.sum_us += other.sum_us;
+        self.max_us = self.max_us.max(other.max_us);
+    }
+
+    /// The average latency, zero if there were no deliveries.
+    pub fn mean(&self) -> Duration {
+        Duration::from_micros(self.sum_us.checked_div(self.count).unwrap_or(0))
+    }
+
+    /// The latency which `p` of the deliveries do not exceed, e.g., 0.99 for the 99th percentile.
+    ///
+    /// The value is interpolated linearly within its bucket, whose upper end is the highest
+    /// latency above the last bound. Zero if there were no deliveries.
+    pub fn percentile(&self, p: f64) -> Duration {
+        let rank = (p.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut below = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            if below + count < rank {
+                below += count;
+                continue;
+            }
+
+            let lower = if i == 0 { 0 } else { LATENCY_BOUNDS_MS[i - 1] * 1000 };
+            let upper = LATENCY_BOUNDS_MS.get(i).map_or(self.max_us, |bound| bound * 1000).min(self.max_us);
+            let fraction = (rank - below) as f64 / count as f64;
+            let us = lower as f64 + upper.saturating_sub(lower) as f64 * fraction;
+            return Duration::from_micros(us as u64);
+        }
+        Duration::ZERO
+    }
+}
+
+/// Serializable copy of [`Metrics`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSnapshot {
+    pub sent_messages: u64,
+    pub sent_bytes: u64,
+    pub dropped_messages: u64,
+    pub dropped_bytes: u64,
+    pub queue_full: u64,
+    /// The number of messages waiting to be delivered
+    pub queue_depth: u64,
+    /// The number of request retries to the brokers, from the statistics
+    pub retries: u64,
+    /// The number of errors by their code, e.g., `MessageTimedOut`
+    pub errors: BTreeMap<String, u64>,
+    /// Sorted by topic and partition
+    pub partitions: Vec<PartitionSnapshot>,
+    /// The latest librdkafka statistics
+    pub statistics: Option<Statistics>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PartitionSnapshot {
+    pub topic: String,
+    /// -1 if the failed message was not assigned to a partition
+    pub partition: i32,
+    pub sent_messages: u64,
+    pub sent_bytes: u64,
+    pub dropped_messages: u64,
+    pub dropped_bytes: u64,
+    pub latency: LatencyHistogram,
+}
+
+/// The name of the error code, e.g., `QueueFull`.
+fn error_code(err: &KafkaError) -> String {
+    match err.rdkafka_error_code() {
+        Some(code) => format!("{:?}", code),
+        None => "Unknown".to_string(),
+    }
+}
+
+impl Metrics {
+    pub(super) fn record_sent(&self, topic: &str, partition: i32, bytes: usize, latency: Duration) {
+        self.sent_messages.fetch_add(1, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+
+        let mut partitions = self.partitions.lock().unwrap();
+        let metrics = partitions.entry((topic.to_string(), partition)).or_default();
+        metrics.sent_messages += 1;
+        metrics.sent_bytes += bytes as u64;
+        metrics.latency.record(latency);
+    }
+
+    pub(super) fn record_dropped(&self, topic: &str, partition: i32, bytes: usize, err: &KafkaError) {
+        self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+        self.dropped_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+
+        let mut partitions = self.partitions.lock().unwrap();
+        let metrics = partitions.entry((topic.to_string(), partition)).or_default();
+        metrics.dropped_messages += 1;
+        metrics.dropped_bytes += bytes as u64;
+        drop(partitions);
+
+        self.record_error(err);
+    }
+
+    /// Counts an error by its code, e.g., when a message cannot be enqueued.
+    pub(super) fn record_error(&self, err: &KafkaError) {
+        *self.errors.lock().unwrap().entry(error_code(err)).or_default() += 1;
+    }
+
+    pub(super) fn record_statistics(&self, statistics: Statistics) {
+        *self.statistics.lock().unwrap() = Some(statistics);
+    }
+
+    /// Copies the counters, the queue depth is that of the latest statistics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut partitions = self
+            .partitions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((topic, partition), metrics)| PartitionSnapshot {
+                topic: topic.clone(),
+                partition: *partition,
+                sent_messages: metrics.sent_messages,
+                sent_bytes: metrics.sent_bytes,
+                dropped_messages: metrics.dropped_messages,
+                dropped_bytes: metrics.dropped_bytes,
+                latency: metrics.latency.clone(),
+            })
+            .collect::<Vec<_>>();
+        partitions.sort_by(|a, b| (&a.topic, a.partition).cmp(&(&b.topic, b.partition)));
+
+        let statistics = self.statistics.lock().unwrap().clone();
+
+        MetricsSnapshot {
+            sent_messages: self.sent_messages.load(Ordering::Relaxed),
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+            dropped_bytes: self.dropped_bytes.load(Ordering::Relaxed),
+            queue_full: self.queue_full.load(Ordering::Relaxed),
+            queue_depth: statistics.as_ref().map_or(0, |x| x.msg_cnt),
+            retries: statistics.as_ref().map_or(0, |x| x.brokers.values().map(|b| b.txretries).sum()),
+            errors: self.errors.lock().unwrap().iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            partitions,
+            statistics,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rdkafka::types::RDKafkaErrorCode;
+
+    use super::*;
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.record(Duration::from_micros(500));
+        histogram.record(Duration::from_millis(3));
+        histogram.record(Duration::from_secs(60));
+
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[2], 1);
+        assert_eq!(histogram.buckets[LATENCY_BOUNDS_MS.len()], 1);
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.mean(), Duration::from_micros(20_001_166));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(0.5), Duration::ZERO);
+
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.percentile(0.5), Duration::from_millis(50));
+        // 50 of the latencies are in (50, 100] ms, 40 of them up to the 90th percentile.
+        assert_eq!(histogram.percentile(0.9), Duration::from_millis(90));
+        assert_eq!(histogram.percentile(1.0), Duration::from_millis(100));
+
+        let mut other = LatencyHistogram::default();
+        other.record(Duration::from_secs(60));
+        histogram.merge(&other);
+        assert_eq!(histogram.count, 101);
+        assert_eq!(histogram.max_us, 60_000_000);
+        assert_eq!(histogram.percentile(1.0), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let metrics = Metrics::default();
+        let timeout = KafkaError::MessageProduction(RDKafkaErrorCode::MessageTimedOut);
+        metrics.record_sent("b", 1, 10, Duration::from_millis(4));
+        metrics.record_sent("a", 0, 20, Duration::from_millis(4));
+        metrics.record_sent("b", 1, 10, Duration::from_millis(6));
+        metrics.record_dropped("b", -1, 5, &timeout);
+        metrics.record_error(&KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.sent_messages, 3);
+        assert_eq!(snapshot.dropped_bytes, 5);
+        assert_eq!(
+            snapshot.partitions.iter().map(|x| (x.topic.as_str(), x.partition)).collect::<Vec<_>>(),
+            [("a", 0), ("b", -1), ("b", 1)]
+        );
+        assert_eq!(snapshot.partitions[2].sent_bytes, 20);
+        assert_eq!(snapshot.partitions[2].latency.mean(), Duration::from_millis(5));
+        assert_eq!(snapshot.errors, BTreeMap::from([("MessageTimedOut".into(), 1), ("QueueFull".into(), 1)]));
+
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["partitions"][1]["droppedMessages"], 1);
+        assert!(json["statistics"].is_null());
+    }
+}
@@ -0,0 +1,534 @@
+//! Record payloads in the Confluent Schema Registry wire format.
+//!
+//! A payload consists of the magic byte `0`, the ID of its schema as a big-endian `u32` and the
+//! encoded value. [`Registered`] wraps any [`Serializer`] or [`Deserializer`] into this format.
+//!
+//! The schemas are registered through a [`SchemaRegistry`]:
+//! * [`HttpSchemaRegistry`], the REST API of a Schema Registry service
+//! * [`MockSchemaRegistry`], an in-process registry for tests
+//! * [`CachedSchemaRegistry`], a cache of the IDs and schemas in front of another registry
+//!
+//! For JSON Schema the value is encoded with [`Json`](super::Json), for Avro with
+//! [`Avro`](super::Avro) against the parsed [`AvroSchema`](super::AvroSchema).
+//!
+//! ## Example
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use ers::kafka::{
+//!     Deserializer, Json, MockSchemaRegistry, Registered, Schema, SchemaSerializer, Serializer,
+//! };
+//!
+//! let registry = Arc::new(MockSchemaRegistry::default());
+//! let schema = Schema::json(r#"{"type": "object"}"#);
+//! let serializer = SchemaSerializer::new(registry, "output_1-value", schema);
+//!
+//! let payload = serializer.register(Json(serde_json::json!({"id": 7}))).unwrap();
+//! let bytes = payload.serialize().unwrap();
+//! assert_eq!(&bytes[..5], &[0, 0, 0, 0, 1]);
+//!
+//! let payload = Registered::<Json<serde_json::Value>>::deserialize(&bytes).unwrap();
+//! assert_eq!(payload.schema_id, 1);
+//! ```
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, OnceLock, RwLock},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::serialization::{Deserializer, SerializationError, Serializer};
+
+/// The first byte of a payload in the wire format
+pub const MAGIC_BYTE: u8 = 0;
+
+const HEADER_LENGTH: usize = 5;
+
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SchemaType {
+    Avro,
+    Json,
+}
+
+impl fmt::Display for SchemaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaType::Avro => write!(f, "AVRO"),
+            SchemaType::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Schema {
+    pub schema_type: SchemaType,
+    /// The schema document, e.g., the JSON Schema
+    pub definition: String,
+}
+
+impl Schema {
+    pub fn avro(definition: impl Into<String>) -> Self {
+        Self { schema_type: SchemaType::Avro, definition: definition.into() }
+    }
+
+    pub fn json(definition: impl Into<String>) -> Self {
+        Self { schema_type: SchemaType::Json, definition: definition.into() }
+    }
+}
+
+/// The subject of the record payloads of a topic, as in the default `TopicNameStrategy`.
+pub fn value_subject(topic: &str) -> String {
+    format!("{}-value", topic)
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    /// There is no schema with the ID.
+    NotFound(u32),
+    /// The registry refused the request, e.g., the schema is invalid or incompatible.
+    Rejected(String),
+    /// The registry is unreachable or its response is invalid.
+    Transport(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::NotFound(id) => write!(f, "Schema {} not found", id),
+            RegistryError::Rejected(err) => write!(f, "Schema rejected: {}", err),
+            RegistryError::Transport(err) => write!(f, "Schema registry unavailable: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// Client of a schema registry.
+pub trait SchemaRegistry: Send + Sync {
+    /// Registers the schema under the subject and returns its ID. Registering the same schema
+    /// again returns the same ID.
+    fn register(&self, subject: &str, schema: &Schema) -> Result<u32, RegistryError>;
+
+    /// Looks up the schema by its ID.
+    fn schema(&self, id: u32) -> Result<Schema, RegistryError>;
+}
+
+impl<T: SchemaRegistry + ?Sized> SchemaRegistry for Arc<T> {
+    fn register(&self, subject: &str, schema: &Schema) -> Result<u32, RegistryError> {
+        (**self).register(subject, schema)
+    }
+
+    fn schema(&self, id: u32) -> Result<Schema, RegistryError> {
+        (**self).schema(id)
+    }
+}
+
+/// In-process registry, the IDs are assigned from 1 in the order of registration.
+#[derive(Default, Debug)]
+pub struct MockSchemaRegistry {
+    state: Mutex<MockState>,
+}
+
+#[derive(Default, Debug)]
+struct MockState {
+    schemas: Vec<Schema>,
+    /// Schema IDs registered under each subject
+    subjects: HashMap<String, Vec<u32>>,
+}
+
+impl MockSchemaRegistry {
+    /// The IDs of the schemas registered under the subject, the oldest first.
+    pub fn versions(&self, subject: &str) -> Vec<u32> {
+        self.state.lock().unwrap().subjects.get(subject).cloned().unwrap_or_default()
+    }
+}
+
+impl SchemaRegistry for MockSchemaRegistry {
+    fn register(&self, subject: &str, schema: &Schema) -> Result<u32, RegistryError> {
+        if schema.schema_type == SchemaType::Json {
+            serde_json::from_str::<serde_json::Value>(&schema.definition)
+                .map_err(|err| RegistryError::Rejected(err.to_string()))?;
+        }
+
+        let state = &mut *self.state.lock().unwrap();
+        let id = match state.schemas.iter().position(|x| x == schema) {
+            Some(index) => index as u32 + 1,
+            None => {
+                state.schemas.push(schema.clone());
+                state.schemas.len() as u32
+            }
+        };
+
+        let versions = state.subjects.entry(subject.to_string()).or_default();
+        if !versions.contains(&id) {
+            versions.push(id);
+        }
+        Ok(id)
+    }
+
+    fn schema(&self, id: u32) -> Result<Schema, RegistryError> {
+        let state = self.state.lock().unwrap();
+        id.checked_sub(1)
+            .and_then(|index| state.schemas.get(index as usize))
+            .cloned()
+            .ok_or(RegistryError::NotFound(id))
+    }
+}
+
+/// Client of the REST API of a Schema Registry service.
+///
+/// The requests are blocking, so it must not be called from an async task.
+pub struct HttpSchemaRegistry {
+    url: reqwest::Url,
+    client: reqwest::blocking::Client,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SchemaBody {
+    schema: String,
+    /// Absent for Avro
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schema_type: Option<SchemaType>,
+}
+
+#[derive(Deserialize)]
+struct IdBody {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+impl HttpSchemaRegistry {
+    /// `url` is the base URL of the service, e.g., `http://localhost:8081`.
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, RegistryError> {
+        let invalid = |err: String| RegistryError::Transport(format!("Invalid registry URL `{}`: {}", url, err));
+        let url = reqwest::Url::parse(url).map_err(|err| invalid(err.to_string()))?;
+        if url.cannot_be_a_base() {
+            return Err(invalid("not a base URL".to_string()));
+        }
+        let client = reqwest::blocking::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|err| RegistryError::Transport(err.to_string()))?;
+
+        Ok(Self { url, client })
+    }
+
+    /// The URL of an API path, each segment is percent-encoded, e.g., a `/` in the subject.
+    fn endpoint(&self, segments: &[&str]) -> reqwest::Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut().expect("Checked to be a base").pop_if_empty().extend(segments);
+        url
+    }
+
+    fn send<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> Result<T, RegistryError> {
+        let transport = |err: reqwest::Error| RegistryError::Transport(err.to_string());
+        let response = request.header(reqwest::header::ACCEPT, CONTENT_TYPE).send().map_err(transport)?;
+
+        if response.status().is_success() {
+            return response.json().map_err(transport);
+        }
+
+        let status = response.status();
+        match response.json::<ErrorBody>() {
+            Ok(err) if status.is_client_error() => Err(RegistryError::Rejected(err.message)),
+            Ok(err) => Err(RegistryError::Transport(err.message)),
+            Err(_) => Err(RegistryError::Transport(status.to_string())),
+        }
+    }
+}
+
+impl SchemaRegistry for HttpSchemaRegistry {
+    fn register(&self, subject: &str, schema: &Schema) -> Result<u32, RegistryError> {
+        let body = SchemaBody {
+            schema: schema.definition.clone(),
+            schema_type: (schema.schema_type != SchemaType::Avro).then_some(schema.schema_type),
+        };
+        let body = serde_json::to_vec(&body).map_err(|err| RegistryError::Rejected(err.to_string()))?;
+
+        let request = self
+            .client
+            .post(self.endpoint(&["subjects", subject, "versions"]))
+            .header(reqwest::header::CONTENT_TYPE, CONTENT_TYPE)
+            .body(body);
+        self.send::<IdBody>(request).map(|x| x.id)
+    }
+
+    fn schema(&self, id: u32) -> Result<Schema, RegistryError> {
+        let request = self.client.get(self.endpoint(&["schemas", "ids", &id.to_string()]));
+        let body = self.send::<SchemaBody>(request).map_err(|err| match err {
+            // The only client error of the lookup
+            RegistryError::Rejected(_) => RegistryError::NotFound(id),
+            err => err,
+        })?;
+
+        Ok(Schema {
+            schema_type: body.schema_type.unwrap_or(SchemaType::Avro),
+            definition: body.schema,
+        })
+    }
+}
+
+/// Keeps the registered IDs and the looked up schemas, so each is requested only once.
+pub struct CachedSchemaRegistry<R> {
+    inner: R,
+    ids: RwLock<HashMap<(String, Schema), u32>>,
+    schemas: RwLock<HashMap<u32, Schema>>,
+}
+
+impl<R: SchemaRegistry> CachedSchemaRegistry<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, ids: Default::default(), schemas: Default::default() }
+    }
+}
+
+impl<R: SchemaRegistry> SchemaRegistry for CachedSchemaRegistry<R> {
+    fn register(&self, subject: &str, schema: &Schema) -> Result<u32, RegistryError> {
+        let key = (subject.to_string(), schema.clone());
+        if let Some(id) = self.ids.read().unwrap().get(&key) {
+            return Ok(*id);
+        }
+
+        let id = self.inner.register(subject, schema)?;
+        self.ids.write().unwrap().insert(key, id);
+        self.schemas.write().unwrap().insert(id, schema.clone());
+        Ok(id)
+    }
+
+    fn schema(&self, id: u32) -> Result<Schema, RegistryError> {
+        if let Some(schema) = self.schemas.read().unwrap().get(&id) {
+            return Ok(schema.clone());
+        }
+
+        let schema = self.inner.schema(id)?;
+        self.schemas.write().unwrap().insert(id, schema.clone());
+        Ok(schema)
+    }
+}
+
+/// A value with the ID of its schema, in the wire format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registered<T> {
+    pub schema_id: u32,
+    pub value: T,
+}
+
+impl<T: Serializer> Serializer for Registered<T> {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError> {
+        let value = self.value.serialize()?;
+        let mut data = Vec::with_capacity(HEADER_LENGTH + value.len());
+        data.push(MAGIC_BYTE);
+        data.extend_from_slice(&self.schema_id.to_be_bytes());
+        data.extend_from_slice(&value);
+        Ok(Cow::Owned(data))
+    }
+}
+
+impl<T: Deserializer> Deserializer for Registered<T> {
+    fn deserialize(data: &[u8]) -> Result<Self, SerializationError> {
+        let (header, value) = data
+            .split_at_checked(HEADER_LENGTH)
+            .ok_or(SerializationError::WireFormat("Insufficient data"))?;
+        if header[0] != MAGIC_BYTE {
+            return Err(SerializationError::WireFormat("Unknown magic byte"));
+        }
+
+        Ok(Registered {
+            schema_id: u32::from_be_bytes(header[1..].try_into().unwrap()),
+            value: T::deserialize(value)?,
+        })
+    }
+}
+
+/// Wraps the values of a subject into [`Registered`], registering the schema on first use.
+pub struct SchemaSerializer {
+    registry: Arc<dyn SchemaRegistry>,
+    subject: String,
+    schema: Schema,
+    id: OnceLock<u32>,
+}
+
+impl SchemaSerializer {
+    pub fn new(registry: Arc<dyn SchemaRegistry>, subject: impl Into<String>, schema: Schema) -> Self {
+        Self { registry, subject: subject.into(), schema, id: OnceLock::new() }
+    }
+
+    /// The ID of the schema, registering it if needed.
+    pub fn schema_id(&self) -> Result<u32, RegistryError> {
+        if let Some(id) = self.id.get() {
+            return Ok(*id);
+        }
+        let id = self.registry.register(&self.subject, &self.schema)?;
+        Ok(*self.id.get_or_init(|| id))
+    }
+
+    pub fn register<T: Serializer>(&self, value: T) -> Result<Registered<T>, RegistryError> {
+        Ok(Registered { schema_id: self.schema_id()?, value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::kafka::{Avro, AvroSchema, Json};
+
+    /// Counts the requests to the inner registry
+    #[derive(Default)]
+    struct Counting {
+        inner: MockSchemaRegistry,
+        requests: AtomicUsize,
+    }
+
+    impl SchemaRegistry for Counting {
+        fn register(&self, subject: &str, schema: &Schema) -> Result<u32, RegistryError> {
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            self.inner.register(subject, schema)
+        }
+
+        fn schema(&self, id: u32) -> Result<Schema, RegistryError> {
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            self.inner.schema(id)
+        }
+    }
+
+    #[test]
+    fn test_mock_registry() {
+        let registry = MockSchemaRegistry::default();
+        let v1 = Schema::json(r#"{"type": "object"}"#);
+        let v2 = Schema::avro(r#""string""#);
+
+        assert_eq!(registry.register("a-value", &v1).unwrap(), 1);
+        assert_eq!(registry.register("a-value", &v2).unwrap(), 2);
+        assert_eq!(registry.register("b-value", &v1).unwrap(), 1);
+        assert_eq!(registry.register("a-value", &v1).unwrap(), 1);
+        assert_eq!(registry.versions("a-value"), [1, 2]);
+        assert_eq!(registry.schema(2).unwrap(), v2);
+        assert!(matches!(registry.schema(3), Err(RegistryError::NotFound(3))));
+        assert!(matches!(registry.register("a-value", &Schema::json("{")), Err(RegistryError::Rejected(_))));
+    }
+
+    #[test]
+    fn test_cached_registry() {
+        let registry = CachedSchemaRegistry::new(Counting::default());
+        let schema = Schema::json("{}");
+
+        for _ in 0..3 {
+            assert_eq!(registry.register("a-value", &schema).unwrap(), 1);
+            assert_eq!(registry.schema(1).unwrap(), schema);
+        }
+        assert_eq!(registry.inner.requests.load(Ordering::Relaxed), 1);
+        assert!(registry.schema(2).is_err());
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let registry = Arc::new(MockSchemaRegistry::default());
+        registry.register("other", &Schema::avro("\"int\"")).unwrap();
+        let serializer = SchemaSerializer::new(registry, "output_1-value", Schema::json("{}"));
+
+        let bytes = serializer.register(Json(json!({"id": 7}))).unwrap().serialize().unwrap().into_owned();
+        assert_eq!(&bytes[..5], &[0, 0, 0, 0, 2]);
+        assert_eq!(&bytes[5..], br#"{"id":7}"#);
+
+        let payload = Registered::<Json<Value>>::deserialize(&bytes).unwrap();
+        assert_eq!(payload, Registered { schema_id: 2, value: Json(json!({"id": 7})) });
+
+        assert!(matches!(
+            Registered::<Vec<u8>>::deserialize(&[0, 0, 1]),
+            Err(SerializationError::WireFormat(_))
+        ));
+        assert!(matches!(
+            Registered::<Vec<u8>>::deserialize(&[1, 0, 0, 0, 1]),
+            Err(SerializationError::WireFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_avro_wire_format() {
+        let definition = r#"{"type": "record", "name": "Event", "fields": [{"name": "id", "type": "int"}]}"#;
+        let schema = AvroSchema::parse(definition).unwrap();
+        let serializer = SchemaSerializer::new(Arc::new(MockSchemaRegistry::default()), "a-value", Schema::avro(definition));
+
+        let bytes = serializer.register(Avro { schema: &schema, value: json!({"id": 7}) }).unwrap().serialize().unwrap().into_owned();
+        assert_eq!(bytes, [0, 0, 0, 0, 1, 14]);
+
+        let payload = Registered::<Vec<u8>>::deserialize(&bytes).unwrap();
+        assert_eq!(schema.decode(&payload.value).unwrap(), json!({"id": 7}));
+        assert!(Avro { schema: &schema, value: json!({"id": "7"}) }.serialize().is_err());
+    }
+
+    #[test]
+    fn test_http_registry() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in [r#"{"id": 3}"#, r#"{"schema": "{}", "schemaType": "JSON"}"#] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                requests.push((head.lines().next().unwrap().to_string(), String::from_utf8(body).unwrap()));
+
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    CONTENT_TYPE,
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+            requests
+        });
+
+        let registry = HttpSchemaRegistry::new(&format!("{}/", url), Duration::from_secs(5)).unwrap();
+        assert_eq!(registry.register("output 1/v7-value", &Schema::json("{}")).unwrap(), 3);
+        assert_eq!(registry.schema(3).unwrap(), Schema::json("{}"));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].0, "POST /subjects/output%201%2Fv7-value/versions HTTP/1.1");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[0].1).unwrap(),
+            json!({"schema": "{}", "schemaType": "JSON"})
+        );
+        assert_eq!(requests[1].0, "GET /schemas/ids/3 HTTP/1.1");
+    }
+}
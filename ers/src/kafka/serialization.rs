@@ -0,0 +1,209 @@
+//! Conversion of record keys and payloads from and to bytes.
+//!
+//! [`Serializer`] is implemented for the types of [`ProducerRecord`](super::ProducerRecord) keys
+//! and payloads, [`Deserializer`] for those of [`ConsumerRecord`](super::ConsumerRecord):
+//! * bytes and strings as they are
+//! * [`Json`], any `serde` type as JSON
+//! * [`Framed`], a sequence of values, each prefixed with its length as [`Varint`]
+//! * [`Avro`](super::Avro), any `serde` type in the Avro binary encoding of a schema
+//!
+//! ## Example
+//!
+//! ```
+//! use ers::kafka::{Deserializer, Framed, Json, Serializer};
+//!
+//! let events = Framed(vec![Json(1), Json(2)]);
+//! let bytes = events.serialize().unwrap();
+//! assert_eq!(&bytes[..], &[1, b'1', 1, b'2']);
+//!
+//! let Framed(events) = Framed::<Json<u32>>::deserialize(&bytes).unwrap();
+//! assert_eq!(events[1].0, 2);
+//! ```
+
+use std::{borrow::Cow, fmt};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Varint;
+
+#[derive(Debug)]
+pub enum SerializationError {
+    Json(serde_json::Error),
+    /// The bytes are not valid UTF-8.
+    Utf8(std::str::Utf8Error),
+    /// The length prefix of a frame is invalid or the frame is truncated.
+    Framing(&'static str),
+    /// The payload is not in the schema registry wire format.
+    WireFormat(&'static str),
+    /// The schema is invalid or the value does not conform to it.
+    Avro(String),
+}
+
+impl fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializationError::Json(err) => write!(f, "Invalid JSON: {}", err),
+            SerializationError::Utf8(err) => write!(f, "Invalid UTF-8: {}", err),
+            SerializationError::Framing(err) => write!(f, "Invalid frame: {}", err),
+            SerializationError::WireFormat(err) => write!(f, "Invalid wire format: {}", err),
+            SerializationError::Avro(err) => write!(f, "Invalid Avro: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SerializationError {}
+
+/// Converts a record key or payload into bytes.
+pub trait Serializer {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError>;
+}
+
+/// Creates a record key or payload from bytes.
+pub trait Deserializer: Sized {
+    fn deserialize(data: &[u8]) -> Result<Self, SerializationError>;
+}
+
+impl Serializer for [u8] {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError> {
+        Ok(Cow::Borrowed(self))
+    }
+}
+
+impl Serializer for Vec<u8> {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError> {
+        Ok(Cow::Borrowed(self))
+    }
+}
+
+impl Serializer for str {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError> {
+        Ok(Cow::Borrowed(self.as_bytes()))
+    }
+}
+
+impl Serializer for String {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError> {
+        Ok(Cow::Borrowed(self.as_bytes()))
+    }
+}
+
+impl<T: Serializer + ?Sized> Serializer for &T {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError> {
+        (**self).serialize()
+    }
+}
+
+impl Deserializer for Vec<u8> {
+    fn deserialize(data: &[u8]) -> Result<Self, SerializationError> {
+        Ok(data.to_vec())
+    }
+}
+
+impl Deserializer for String {
+    fn deserialize(data: &[u8]) -> Result<Self, SerializationError> {
+        std::str::from_utf8(data)
+            .map(str::to_string)
+            .map_err(SerializationError::Utf8)
+    }
+}
+
+/// A value serialized as JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> Serializer for Json<T> {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError> {
+        serde_json::to_vec(&self.0)
+            .map(Cow::Owned)
+            .map_err(SerializationError::Json)
+    }
+}
+
+impl<T: DeserializeOwned> Deserializer for Json<T> {
+    fn deserialize(data: &[u8]) -> Result<Self, SerializationError> {
+        serde_json::from_slice(data)
+            .map(Json)
+            .map_err(SerializationError::Json)
+    }
+}
+
+/// A sequence of values, each prefixed with its length as [`Varint`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framed<T>(pub Vec<T>);
+
+impl<T: Serializer> Serializer for Framed<T> {
+    fn serialize(&self) -> Result<Cow<'_, [u8]>, SerializationError> {
+        let mut data = Vec::new();
+        for item in &self.0 {
+            let bytes = item.serialize()?;
+            data.extend(Varint::from(bytes.len() as u64).to_bytes());
+            data.extend_from_slice(&bytes);
+        }
+        Ok(Cow::Owned(data))
+    }
+}
+
+impl<T: Deserializer> Deserializer for Framed<T> {
+    fn deserialize(mut data: &[u8]) -> Result<Self, SerializationError> {
+        let mut items = Vec::new();
+
+        while !data.is_empty() {
+            let length = Varint::try_from(data).map_err(SerializationError::Framing)?;
+            let start = length.length() as usize;
+            let end = usize::try_from(length.value())
+                .ok()
+                .and_then(|x| x.checked_add(start))
+                .filter(|end| *end <= data.len())
+                .ok_or(SerializationError::Framing("Insufficient data"))?;
+
+            items.push(T::deserialize(&data[start..end])?);
+            data = &data[end..];
+        }
+
+        Ok(Framed(items))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        id: u32,
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let bytes = Json(Event { id: 7 }).serialize().unwrap();
+        assert_eq!(&bytes[..], br#"{"id":7}"#);
+        assert_eq!(Json::<Event>::deserialize(&bytes).unwrap().0, Event { id: 7 });
+        assert!(matches!(Json::<Event>::deserialize(b"{"), Err(SerializationError::Json(_))));
+    }
+
+    #[test]
+    fn test_framed_long_frames() {
+        let items = vec![vec![b'a'; 300], Vec::new(), vec![b'b'; 20000]];
+        let framed = Framed(items.clone());
+        let bytes = framed.serialize().unwrap();
+        assert_eq!(bytes.len(), 2 + 300 + 1 + 3 + 20000);
+        assert_eq!(Framed::<Vec<u8>>::deserialize(&bytes).unwrap().0, items);
+    }
+
+    #[test]
+    fn test_framed_truncated() {
+        let framed = Framed(vec!["abc"]);
+        let bytes = framed.serialize().unwrap();
+        assert!(matches!(
+            Framed::<String>::deserialize(&bytes[..3]),
+            Err(SerializationError::Framing(_))
+        ));
+    }
+
+    #[test]
+    fn test_string_invalid_utf8() {
+        assert!(matches!(<String as Deserializer>::deserialize(&[0xFF]), Err(SerializationError::Utf8(_))));
+    }
+}
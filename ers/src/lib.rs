@@ -4,7 +4,10 @@
 //!
 //! The library provides APIs for various interfaces, generic types and functionalities for easy
 //! microservice development adhering to Ericsson requirements:
-//! * Kafka
+//! * Kafka (idempotent and transactional producer)
+//! * Layered configuration loading
+//! * Structured logging (ADP log format)
+//! * Distributed tracing (W3C trace context, OTLP)
 //! * _Others coming soon_
 //!
 //! ## Examples
@@ -17,7 +20,10 @@
 //!   * [`std::str::FromStr`]
 //!   * [`std::fmt::Display`]
 
+pub mod config;
 pub mod kafka;
+pub mod logging;
+pub mod trace;
 
 mod types;
 
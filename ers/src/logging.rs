@@ -0,0 +1,292 @@
+//! Logging in text or in the structured ADP JSON format.
+//!
+//! The logger is the backend of the [`log`] facade, so the usual `log::info!` macros are used.
+//! Filtering follows the `RUST_LOG` syntax (e.g., `info,rdkafka=warn`) and can be changed at
+//! runtime with [`set_level`].
+//!
+//! ## JSON record
+//!
+//! ```json
+//! {"version":"1.2.0","timestamp":"2024-01-30T12:00:00.000+00:00","severity":"info","service_id":"eric-rust-chassis","message":"Server is live"}
+//! ```
+//!
+//! Key-values of the record are added under `extra_data`, e.g.,
+//! `log::info!(topic = "output_1"; "Message sent")`.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt,
+    io::Write,
+    sync::{OnceLock, RwLock},
+};
+
+use chrono::{Local, SecondsFormat, Utc};
+use env_logger::filter::{Builder, Filter};
+use log::{
+    kv::{Key, Value as KvValue, VisitSource},
+    Level, LevelFilter, Log, Metadata, Record,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Version of the ADP log event format.
+pub const LOG_FORMAT_VERSION: &str = "1.2.0";
+
+/// Environment variable of the service ID, set by the Helm chart.
+const SERVICE_ID_ENV: &str = "SERVICE_ID";
+
+/// Environment variable overriding the configured filter.
+const FILTER_ENV: &str = "RUST_LOG";
+
+static LOGGER: OnceLock<ChassisLogger> = OnceLock::new();
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable, similar to `env_logger`
+    #[default]
+    Text,
+    /// One ADP log event per line
+    Json,
+}
+
+/// The `log` section of the service configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// Filter directives in `RUST_LOG` syntax, `RUST_LOG` takes precedence if set
+    pub level: String,
+    pub format: LogFormat,
+    /// Defaults to the `SERVICE_ID` environment variable, then to the name given at [`init`]
+    pub service_id: Option<String>,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: String::from("info"),
+            format: LogFormat::default(),
+            service_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum LoggingError {
+    /// A logger has already been set for the process.
+    AlreadyInitialized,
+    /// The filter directive is invalid.
+    InvalidFilter(String),
+    /// [`init`] has not been called.
+    NotInitialized,
+}
+
+impl fmt::Display for LoggingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoggingError::AlreadyInitialized => write!(f, "Logger is already initialized"),
+            LoggingError::InvalidFilter(directive) => write!(f, "Invalid log filter directive: `{}`", directive),
+            LoggingError::NotInitialized => write!(f, "Logger is not initialized"),
+        }
+    }
+}
+
+impl std::error::Error for LoggingError {}
+
+struct ActiveFilter {
+    spec: String,
+    filter: Filter,
+}
+
+struct ChassisLogger {
+    format: LogFormat,
+    service_id: String,
+    filter: RwLock<ActiveFilter>,
+}
+
+impl Log for ChassisLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.read().unwrap().filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.read().unwrap().filter.matches(record) {
+            return;
+        }
+
+        let line = match self.format {
+            LogFormat::Text => format_text(record),
+            LogFormat::Json => format_json(record, &self.service_id).to_string(),
+        };
+
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Checks the directives, as `env_logger` silently ignores the invalid ones.
+fn parse_filter(spec: &str) -> Result<Filter, LoggingError> {
+    let directives = spec.split('/').next().unwrap_or_default();
+
+    for directive in directives.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+        let valid = match directive.split_once('=') {
+            Some((module, level)) => !module.is_empty() && level.parse::<LevelFilter>().is_ok(),
+            None => !directive.contains(char::is_whitespace),
+        };
+
+        if !valid {
+            return Err(LoggingError::InvalidFilter(directive.to_string()));
+        }
+    }
+
+    Ok(Builder::new().parse(spec).build())
+}
+
+/// Installs the logger, call it once at the start of `main`.
+pub fn init(settings: &LogSettings, default_service_id: &str) -> Result<(), LoggingError> {
+    let spec = env::var(FILTER_ENV).unwrap_or_else(|_| settings.level.clone());
+    let filter = parse_filter(&spec)?;
+    let service_id = settings
+        .service_id
+        .clone()
+        .or_else(|| env::var(SERVICE_ID_ENV).ok())
+        .unwrap_or_else(|| default_service_id.to_string());
+
+    let max_level = filter.filter();
+    let logger = ChassisLogger {
+        format: settings.format,
+        service_id,
+        filter: RwLock::new(ActiveFilter { spec, filter }),
+    };
+
+    LOGGER.set(logger).map_err(|_| LoggingError::AlreadyInitialized)?;
+    log::set_logger(LOGGER.get().expect("Logic error!")).map_err(|_| LoggingError::AlreadyInitialized)?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Replaces the active filter, e.g., `debug` or `info,ves=trace`.
+pub fn set_level(spec: &str) -> Result<(), LoggingError> {
+    let logger = LOGGER.get().ok_or(LoggingError::NotInitialized)?;
+    let filter = parse_filter(spec)?;
+
+    log::set_max_level(filter.filter());
+    *logger.filter.write().unwrap() = ActiveFilter { spec: spec.to_string(), filter };
+    Ok(())
+}
+
+/// The active filter directives.
+pub fn level() -> Result<String, LoggingError> {
+    let logger = LOGGER.get().ok_or(LoggingError::NotInitialized)?;
+    let spec = logger.filter.read().unwrap().spec.clone();
+    Ok(spec)
+}
+
+/// Severities of the ADP log format.
+fn severity(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warning",
+        Level::Info => "info",
+        Level::Debug | Level::Trace => "debug",
+    }
+}
+
+struct KeyValues(BTreeMap<String, String>);
+
+impl<'kvs> VisitSource<'kvs> for KeyValues {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: KvValue<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+fn key_values(record: &Record) -> BTreeMap<String, String> {
+    let mut visitor = KeyValues(BTreeMap::new());
+    let _ = record.key_values().visit(&mut visitor);
+    visitor.0
+}
+
+fn format_text(record: &Record) -> String {
+    let mut line = format!(
+        "[{} {:<5} {}] {}",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        record.level(),
+        record.target(),
+        record.args()
+    );
+    for (key, value) in key_values(record) {
+        line += &format!(" {}={}", key, value);
+    }
+    line
+}
+
+/// Creates an ADP log event from the record.
+pub fn format_json(record: &Record, service_id: &str) -> Value {
+    let mut event = json!({
+        "version": LOG_FORMAT_VERSION,
+        "timestamp": Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+        "severity": severity(record.level()),
+        "service_id": service_id,
+        "message": record.args().to_string(),
+    });
+
+    let extra_data = key_values(record);
+    if !extra_data.is_empty() {
+        event["extra_data"] = json!(extra_data);
+    }
+
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_record() {
+        let kvs = [("topic", "output_1")];
+        let record = Record::builder()
+            .args(format_args!("Message sent"))
+            .level(Level::Warn)
+            .key_values(&kvs)
+            .build();
+
+        let event = format_json(&record, "eric-rust-chassis");
+        assert_eq!(event["version"], LOG_FORMAT_VERSION);
+        assert_eq!(event["severity"], "warning");
+        assert_eq!(event["service_id"], "eric-rust-chassis");
+        assert_eq!(event["message"], "Message sent");
+        assert_eq!(event["extra_data"], json!({ "topic": "output_1" }));
+        assert!(event["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_json_record_without_extra_data() {
+        let record = Record::builder()
+            .args(format_args!("Healthy"))
+            .level(Level::Info)
+            .build();
+
+        assert!(format_json(&record, "ves").get("extra_data").is_none());
+    }
+
+    #[test]
+    fn test_filter_validation() {
+        assert!(parse_filter("debug").is_ok());
+        assert!(parse_filter("info,rdkafka=warn").is_ok());
+        assert_eq!(parse_filter("rdkafka=loud").err(), Some(LoggingError::InvalidFilter("rdkafka=loud".into())));
+        assert!(parse_filter("=info").is_err());
+    }
+
+    #[test]
+    fn test_settings_format() {
+        let settings: LogSettings = serde_json::from_value(json!({ "format": "json" })).unwrap();
+        assert_eq!(settings.format, LogFormat::Json);
+        assert_eq!(settings.level, "info");
+    }
+}
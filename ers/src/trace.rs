@@ -0,0 +1,211 @@
+//! Distributed tracing with W3C trace context propagation.
+//!
+//! Spans are created with the [`tracing`](::tracing) crate and exported by OpenTelemetry over
+//! OTLP/HTTP, e.g., to a local collector listening on `http://localhost:4318`. The trace context
+//! travels in the `traceparent` and `tracestate` headers of HTTP requests and Kafka records.
+//!
+//! Even if exporting is disabled, the context of incoming requests is passed on, so a trace is
+//! not broken by a service which does not export its own spans.
+//!
+//! ## Example
+//!
+//! ```
+//! use std::collections::HashMap;
+//! use ers::trace;
+//!
+//! let span = tracing::info_span!("process");
+//! let mut headers = HashMap::new();
+//! trace::inject(&span, &mut headers);
+//! ```
+
+use std::{collections::HashMap, fmt};
+
+use opentelemetry::{
+    propagation::{Extractor, Injector, TextMapPropagator},
+    trace::TracerProvider,
+    Context,
+};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use serde::{Deserialize, Serialize};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// The `trace` section of the service configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TraceSettings {
+    /// Export spans to the OTLP endpoint
+    pub enable: bool,
+    /// OTLP/HTTP traces endpoint of the collector
+    pub endpoint: String,
+}
+
+impl Default for TraceSettings {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            endpoint: String::from("http://localhost:4318/v1/traces"),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum TraceError {
+    /// A tracing subscriber has already been set for the process.
+    AlreadyInitialized,
+    /// The OTLP exporter cannot be created.
+    Exporter(String),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::AlreadyInitialized => write!(f, "Tracing is already initialized"),
+            TraceError::Exporter(err) => write!(f, "Failed to create OTLP exporter: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+/// Flushes the pending spans when dropped, keep it alive until the end of `main`.
+pub struct TraceGuard {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        if let Err(err) = self.provider.shutdown() {
+            log::warn!("Failed to shut down tracing: {}", err);
+        }
+    }
+}
+
+/// Installs the global tracing subscriber.
+///
+/// It must not be called from an async context, as the blocking HTTP client of the exporter
+/// cannot be created there.
+pub fn init(settings: &TraceSettings, service_name: &str) -> Result<TraceGuard, TraceError> {
+    let mut builder = SdkTracerProvider::builder()
+        .with_resource(Resource::builder().with_service_name(service_name.to_string()).build());
+
+    if settings.enable {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(settings.endpoint.as_str())
+            .build()
+            .map_err(|err| TraceError::Exporter(err.to_string()))?;
+        builder = builder.with_batch_exporter(exporter);
+    }
+
+    let provider = builder.build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(service_name.to_string()));
+
+    ::tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .map_err(|_| TraceError::AlreadyInitialized)?;
+
+    Ok(TraceGuard { provider })
+}
+
+/// [`Injector`] for record headers, e.g., [`crate::kafka::ProducerRecord`].
+pub struct HeaderMapCarrier<'a>(pub &'a mut HashMap<String, Vec<u8>>);
+
+impl Injector for HeaderMapCarrier<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_string(), value.into_bytes());
+    }
+}
+
+/// [`Extractor`] for record headers, e.g., [`crate::kafka::ProducerRecord`].
+pub struct HeaderMapExtractor<'a>(pub &'a HashMap<String, Vec<u8>>);
+
+impl Extractor for HeaderMapExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+}
+
+/// Writes the trace context into the carrier.
+pub fn inject_context(cx: &Context, carrier: &mut dyn Injector) {
+    TraceContextPropagator::new().inject_context(cx, carrier);
+}
+
+/// Reads the trace context from the carrier, empty if there is none.
+pub fn extract_context(carrier: &dyn Extractor) -> Context {
+    TraceContextPropagator::new().extract(carrier)
+}
+
+/// Writes the context of `span` into the headers.
+pub fn inject(span: &::tracing::Span, headers: &mut HashMap<String, Vec<u8>>) {
+    inject_context(&span.context(), &mut HeaderMapCarrier(headers));
+}
+
+/// Reads the trace context from the headers.
+pub fn extract(headers: &HashMap<String, Vec<u8>>) -> Context {
+    extract_context(&HeaderMapExtractor(headers))
+}
+
+/// Makes `span` the child of the remote context read from `carrier`.
+pub fn set_remote_parent(span: &::tracing::Span, carrier: &dyn Extractor) {
+    let _ = span.set_parent(extract_context(carrier));
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TraceContextExt;
+
+    use super::*;
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    #[test]
+    fn test_roundtrip() {
+        let mut headers = HashMap::new();
+        headers.insert("traceparent".to_string(), TRACEPARENT.as_bytes().to_vec());
+
+        let cx = extract(&headers);
+        assert_eq!(cx.span().span_context().trace_id().to_string(), "0af7651916cd43dd8448eb211c80319c");
+
+        let mut output = HashMap::new();
+        inject_context(&cx, &mut HeaderMapCarrier(&mut output));
+        assert_eq!(output.get("traceparent"), Some(&TRACEPARENT.as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_span_propagation() {
+        let provider = SdkTracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        let mut headers = HashMap::new();
+        headers.insert("traceparent".to_string(), TRACEPARENT.as_bytes().to_vec());
+
+        let output = ::tracing::subscriber::with_default(subscriber, || {
+            let span = ::tracing::info_span!("test");
+            set_remote_parent(&span, &HeaderMapExtractor(&headers));
+
+            let mut output = HashMap::new();
+            inject(&span, &mut output);
+            output
+        });
+
+        let traceparent = String::from_utf8(output["traceparent"].clone()).unwrap();
+        assert!(traceparent.starts_with("00-0af7651916cd43dd8448eb211c80319c-"));
+        assert!(!traceparent.contains("b7ad6b7169203331"));
+    }
+
+    #[test]
+    fn test_missing_context() {
+        let cx = extract(&HashMap::new());
+        assert!(!cx.span().span_context().is_valid());
+
+        let mut output = HashMap::new();
+        inject_context(&cx, &mut HeaderMapCarrier(&mut output));
+        assert!(output.is_empty());
+    }
+}
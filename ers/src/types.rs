@@ -6,6 +6,8 @@ use std::str::FromStr;
 pub const MSB_MASK: u8 = 0b1000_0000;
 const VARINT_PAYLOAD_MASK: u8 = 0b0111_1111;
 const VARINT_PAYLOAD_BITS: u8 = 7;
+/// The longest varint fitting into `i128`
+const VARINT_MAX_LENGTH: u8 = 19;
 
 /// Semantic version.
 ///
@@ -43,7 +45,7 @@ const VARINT_PAYLOAD_BITS: u8 = 7;
 ///
 /// <https://semver.org>
 ///
-#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub struct SemVer {
     pub major: u16,
     pub minor: u16,
@@ -91,6 +93,18 @@ impl FromStr for SemVer {
     }
 }
 
+impl fmt::Display for SemVer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Default for SemVer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SemVer {
 
     /// Creates a default initialized `SemVer`.
@@ -151,13 +165,17 @@ impl TryFrom<&[u8]> for Varint {
         let mut continuation_bit = true;
 
         while continuation_bit {
+            if length >= VARINT_MAX_LENGTH {
+                return Err("Too long");
+            }
+
             if length as usize >= data.len() {
                 return Err("Insufficient data");
             }
 
             let byte = data[length as usize];
             continuation_bit = byte & MSB_MASK != 0;
-            value += ((byte & VARINT_PAYLOAD_MASK) << length * VARINT_PAYLOAD_BITS) as i128;
+            value += ((byte & VARINT_PAYLOAD_MASK) as i128) << (length * VARINT_PAYLOAD_BITS);
 
             length += 1;
         }
@@ -173,9 +191,47 @@ impl From<Varint> for i128 {
     }
 }
 
-// TODO(feat): encode into varint as bytes
+impl From<u64> for Varint {
+    /// Construct a [`Varint`] from a non-negative integer.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use ers::Varint;
+    /// let x = Varint::from(150);
+    /// assert_eq!(x.to_bytes(), vec![0b1001_0110, 0b0000_0001]);
+    /// assert_eq!(x.length(), 2);
+    /// ```
+    fn from(value: u64) -> Self {
+        let mut length = 1;
+        let mut rest = value >> VARINT_PAYLOAD_BITS;
+        while rest != 0 {
+            length += 1;
+            rest >>= VARINT_PAYLOAD_BITS;
+        }
+
+        Self{ value: value as i128, length }
+    }
+}
 
 impl Varint {
+    /// Encodes the value, the inverse of `TryFrom<&[u8]>`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.length as usize);
+        let mut rest = self.value;
+
+        loop {
+            let byte = (rest & VARINT_PAYLOAD_MASK as i128) as u8;
+            rest >>= VARINT_PAYLOAD_BITS;
+
+            if rest == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | MSB_MASK);
+        }
+    }
+
     pub fn value(&self) -> i128 {
         self.value
     }
@@ -218,6 +274,28 @@ mod tests {
         assert_eq!(x.length(), 2);
     }
 
+    #[test]
+    fn test_varint_decode_3_bytes() {
+        let x = Varint::try_from(vec![0b1000_0000, 0b1000_0000, 0b0000_0001].as_slice()).unwrap();
+        assert_eq!(x.value(), 1 << 14);
+        assert_eq!(x.length(), 3);
+    }
+
+    #[test]
+    fn test_varint_decode_too_long() {
+        assert_eq!(Varint::try_from(vec![0xFF; 20].as_slice()), Err("Too long"));
+    }
+
+    #[test]
+    fn test_varint_roundtrip() {
+        for value in [0, 1, 127, 128, 300, 1 << 21, u32::MAX as u64, u64::MAX] {
+            let bytes = Varint::from(value).to_bytes();
+            let x = Varint::try_from(bytes.as_slice()).unwrap();
+            assert_eq!(x.value(), value as i128);
+            assert_eq!(x.length() as usize, bytes.len());
+        }
+    }
+
     #[test]
     fn test_varint_decode_conversion_into() {
         let varint: Varint = vec![0b1001_0110, 0b0000_0001].as_slice().try_into().unwrap();
@@ -0,0 +1,195 @@
+//! Load test of the VES event listener with generated VES 7.2 events.
+//!
+//! The workers send single events to `/eventListener/v7` or batches to
+//! `/eventListener/v7/eventBatch` at the target rate, shared between them, until the number of
+//! requests is sent or the duration has elapsed. Then the accepted and rejected requests are
+//! reported with the latency percentiles, estimated from a latency histogram.
+//!
+//! ```sh
+//! ves_load --url http://localhost:8080 --domains fault,measurement --mode batch --batch-size 10 --rate 500 --concurrency 8 --duration 60
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, sleep},
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use ers::kafka::LatencyHistogram;
+use ves::generator::{Domain, EventGenerator};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Mode {
+    /// One event per request to `/eventListener/v7`
+    Event,
+    /// `--batch-size` events per request to `/eventListener/v7/eventBatch`
+    Batch,
+}
+
+#[derive(clap::Parser)]
+struct Args {
+    /// The address of the VES event listener
+    #[arg(short, long, default_value_t = String::from("http://localhost:8080"))]
+    url: String,
+
+    /// The domains of the events, comma-separated
+    #[arg(long, value_delimiter = ',', default_values_t = Domain::ALL)]
+    domains: Vec<Domain>,
+
+    #[arg(short, long, value_enum, default_value_t = Mode::Event)]
+    mode: Mode,
+
+    #[arg(short, long, default_value_t = 10)]
+    batch_size: usize,
+
+    /// Target rate in requests per second for all workers together, unlimited if 0
+    #[arg(short, long, default_value_t = 0)]
+    rate: u64,
+
+    /// The number of workers sending requests in parallel
+    #[arg(short, long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Number of requests to send, 1 by default unless a duration is given
+    #[arg(short, long)]
+    num_requests: Option<u64>,
+
+    /// How long to send in seconds
+    #[arg(short, long)]
+    duration: Option<f64>,
+
+    /// Request timeout in seconds
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+}
+
+/// The results of a worker.
+#[derive(Default)]
+struct Report {
+    accepted: u64,
+    /// The number of rejected requests by status code
+    rejected: BTreeMap<u16, u64>,
+    /// Requests without a response, e.g., connection refused or timeout
+    failed: u64,
+    /// Response times of the requests with a response
+    latency: LatencyHistogram,
+}
+
+impl Report {
+    fn merge(&mut self, other: Report) {
+        self.accepted += other.accepted;
+        for (status, count) in other.rejected {
+            *self.rejected.entry(status).or_default() += count;
+        }
+        self.failed += other.failed;
+        self.latency.merge(&other.latency);
+    }
+}
+
+fn run_worker(id: usize, args: &Args, issued: &AtomicU64, start: Instant) -> Report {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(args.timeout))
+        .build()
+        .expect("Failed to create the HTTP client");
+    let url = match args.mode {
+        Mode::Event => format!("{}/eventListener/v7", args.url.trim_end_matches('/')),
+        Mode::Batch => format!("{}/eventListener/v7/eventBatch", args.url.trim_end_matches('/')),
+    };
+
+    let limit = args.num_requests.or(if args.duration.is_some() { None } else { Some(1) });
+    let duration = args.duration.map(Duration::from_secs_f64);
+    let interval = (args.rate > 0).then(|| Duration::from_secs_f64(1.0 / args.rate as f64));
+    let mut generator = EventGenerator::new(format!("ves-load-{}", id));
+    let mut report = Report::default();
+
+    loop {
+        let index = issued.fetch_add(1, Ordering::Relaxed);
+        if limit.is_some_and(|limit| index >= limit) {
+            return report;
+        }
+        if let Some(interval) = interval {
+            let due = start + interval.mul_f64(index as f64);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                sleep(wait);
+            }
+        }
+        if duration.is_some_and(|x| start.elapsed() >= x) {
+            return report;
+        }
+
+        let body = match args.mode {
+            Mode::Event => generator.request(&args.domains),
+            Mode::Batch => generator.batch(&args.domains, args.batch_size),
+        };
+        let sent = Instant::now();
+        let response = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header("X-MinorVersion", "2")
+            .body(body.to_string())
+            .send();
+
+        match response {
+            Ok(response) if response.status().is_success() => {
+                report.accepted += 1;
+                report.latency.record(sent.elapsed());
+            }
+            Ok(response) => {
+                *report.rejected.entry(response.status().as_u16()).or_default() += 1;
+                report.latency.record(sent.elapsed());
+                log::debug!("Rejected: {}", response.text().unwrap_or_default());
+            }
+            Err(err) => {
+                log::debug!("Request failed: {}", err);
+                report.failed += 1;
+            }
+        }
+    }
+}
+
+fn main() {
+    let args = Arc::new(Args::parse());
+    ers::logging::init(&ers::logging::LogSettings::default(), "ves_load").expect("Failed to initialize logging");
+
+    let issued = Arc::new(AtomicU64::new(0));
+    let start = Instant::now();
+    let workers = (0..args.concurrency.max(1))
+        .map(|id| {
+            let args = Arc::clone(&args);
+            let issued = Arc::clone(&issued);
+            thread::spawn(move || run_worker(id, &args, &issued, start))
+        })
+        .collect::<Vec<_>>();
+
+    let mut report = Report::default();
+    for worker in workers {
+        report.merge(worker.join().expect("Worker panicked"));
+    }
+    let seconds = start.elapsed().as_secs_f64();
+
+    let rejected = report.rejected.values().sum::<u64>();
+    let requests = report.accepted + rejected + report.failed;
+    let events = match args.mode {
+        Mode::Event => report.accepted,
+        Mode::Batch => report.accepted * args.batch_size as u64,
+    };
+    println!("Requests:   {} sent, {} accepted, {} rejected, {} failed", requests, report.accepted, rejected, report.failed);
+    for (status, count) in &report.rejected {
+        println!("Rejected:   {} x {}", count, status);
+    }
+    println!("Time:       {:.3} s", seconds);
+    println!("Throughput: {:.0} requests/s, {:.0} accepted events/s", requests as f64 / seconds, events as f64 / seconds);
+    println!(
+        "Latency:    p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, p99.9 {:.2?}, max {:.2?}",
+        report.latency.percentile(0.5),
+        report.latency.percentile(0.9),
+        report.latency.percentile(0.99),
+        report.latency.percentile(0.999),
+        Duration::from_micros(report.latency.max_us),
+    );
+}
@@ -0,0 +1,101 @@
+//! Decoding of compressed request bodies.
+//!
+//! Supported `Content-Encoding`s are `gzip`, `deflate` and `zstd`. The decompressed size is
+//! limited the same way as the size of the received body, so a small compressed request cannot
+//! expand into an arbitrarily large event (zip bomb).
+
+use std::io::Read;
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use crate::ves::{invalid_compressed_content, message_size_exceeded, unsupported_content_encoding, VesError};
+
+pub const HEADER_CONTENT_ENCODING: &str = "Content-Encoding";
+
+/// Decodes `data` according to the `Content-Encoding`, at most `limit` bytes are produced.
+///
+/// ## Example
+///
+/// ```
+/// # use ves::compression::decode_body;
+/// assert_eq!(decode_body(None, b"{}".to_vec(), 2).unwrap(), b"{}");
+/// assert!(decode_body(Some("br"), b"{}".to_vec(), 2).is_err());
+/// ```
+pub fn decode_body(encoding: Option<&str>, data: Vec<u8>, limit: usize) -> Result<Vec<u8>, VesError> {
+    let encoding = encoding.map(|x| x.trim().to_ascii_lowercase());
+
+    let decoded = match encoding.as_deref() {
+        None | Some("") | Some("identity") => return Ok(data),
+        Some("gzip") | Some("x-gzip") => read_limited(GzDecoder::new(data.as_slice()), limit),
+        Some("deflate") => read_limited(ZlibDecoder::new(data.as_slice()), limit),
+        Some("zstd") => zstd::stream::read::Decoder::with_buffer(data.as_slice())
+            .map_err(|_| invalid_compressed_content())
+            .and_then(|decoder| read_limited(decoder, limit)),
+        Some(_) => Err(unsupported_content_encoding()),
+    }?;
+
+    log::debug!("Request body decompressed from {} to {} bytes", data.len(), decoded.len());
+    Ok(decoded)
+}
+
+fn read_limited(reader: impl Read, limit: usize) -> Result<Vec<u8>, VesError> {
+    let mut decoded = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|_| invalid_compressed_content())?;
+
+    if decoded.len() > limit {
+        return Err(message_size_exceeded());
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::{GzEncoder, ZlibEncoder}, Compression};
+
+    use super::*;
+
+    const EVENT: &[u8] = include_bytes!("../res/ves-7.2.1-domain_pnfRegistration.json");
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_gzip() {
+        assert_eq!(decode_body(Some("gzip"), gzip(EVENT), EVENT.len()).unwrap(), EVENT);
+    }
+
+    #[test]
+    fn test_deflate() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(EVENT).unwrap();
+        let data = encoder.finish().unwrap();
+        assert_eq!(decode_body(Some("Deflate"), data, EVENT.len()).unwrap(), EVENT);
+    }
+
+    #[test]
+    fn test_zstd() {
+        let data = zstd::encode_all(EVENT, 0).unwrap();
+        assert_eq!(decode_body(Some("zstd"), data, EVENT.len()).unwrap(), EVENT);
+    }
+
+    #[test]
+    fn test_decompressed_size_limit() {
+        let bomb = gzip(&vec![b' '; 1024 * 1024]);
+        assert!(bomb.len() < 4096);
+        assert_eq!(decode_body(Some("gzip"), bomb, 4096), Err(message_size_exceeded()));
+    }
+
+    #[test]
+    fn test_corrupt_content() {
+        assert_eq!(decode_body(Some("gzip"), EVENT.to_vec(), EVENT.len()), Err(invalid_compressed_content()));
+    }
+}
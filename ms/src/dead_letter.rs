@@ -0,0 +1,118 @@
+//! Records of the dead-letter topic.
+//!
+//! Events which are rejected by the validation, are duplicates (with `dedup.dead_letter`, see
+//! [`crate::dedup`]) or cannot be delivered to the broker are sent to the
+//! `interfaces.northbound.kafka.dead_letter` topic, so they can be inspected or replayed.
+//! The payload is the original one, the headers tell why and when it was received:
+//!
+//! | Header                 | Value                                                  |
+//! |------------------------|--------------------------------------------------------|
+//! | `ves-version`          | Requested VES version, e.g., `v7.2.1`                  |
+//! | `ves-received-at`      | Time of receiving the request in RFC 3339              |
+//! | `ves-failure-reason`   | `validation`, `duplicate` or `delivery`                |
+//! | `ves-failure-detail`   | The VES exception or the Kafka error                   |
+//! | `ves-original-topic`   | Topic of the undelivered record (only for `delivery`)  |
+//!
+//! The version and the receive time are set on every forwarded record, so they are available
+//! when the delivery fails.
+
+use std::{collections::HashMap, fmt};
+
+use chrono::{SecondsFormat, Utc};
+
+use ers::kafka::ProducerRecord;
+
+pub const HEADER_VERSION: &str = "ves-version";
+pub const HEADER_RECEIVED_AT: &str = "ves-received-at";
+pub const HEADER_FAILURE_REASON: &str = "ves-failure-reason";
+pub const HEADER_FAILURE_DETAIL: &str = "ves-failure-detail";
+pub const HEADER_ORIGINAL_TOPIC: &str = "ves-original-topic";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FailureReason {
+    /// The event was rejected by the validation
+    Validation,
+    /// The event was accepted recently
+    Duplicate,
+    /// The record could not be delivered to the broker
+    Delivery,
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::Validation => write!(f, "validation"),
+            FailureReason::Duplicate => write!(f, "duplicate"),
+            FailureReason::Delivery => write!(f, "delivery"),
+        }
+    }
+}
+
+/// Adds the version and the current time to the headers of a received event.
+pub fn set_origin(headers: &mut HashMap<String, Vec<u8>>, version: &str) {
+    headers.insert(HEADER_VERSION.to_string(), version.as_bytes().to_vec());
+    headers.insert(
+        HEADER_RECEIVED_AT.to_string(),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into_bytes(),
+    );
+}
+
+/// Redirects the record to the dead-letter `topic`, keeping its payload and headers.
+pub fn to_dead_letter(
+    mut record: ProducerRecord,
+    topic: &str,
+    reason: FailureReason,
+    detail: &str,
+) -> ProducerRecord {
+    if reason == FailureReason::Delivery {
+        record.headers.insert(HEADER_ORIGINAL_TOPIC.to_string(), record.topic.into_bytes());
+    }
+    record.headers.insert(HEADER_FAILURE_REASON.to_string(), reason.to_string().into_bytes());
+    record.headers.insert(HEADER_FAILURE_DETAIL.to_string(), detail.as_bytes().to_vec());
+    record.topic = topic.to_string();
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header<'a>(record: &'a ProducerRecord, key: &str) -> &'a str {
+        std::str::from_utf8(&record.headers[key]).unwrap()
+    }
+
+    #[test]
+    fn test_delivery_failure() {
+        let mut headers = HashMap::new();
+        set_origin(&mut headers, "v7.2.1");
+        let record = ProducerRecord {
+            key: "test".into(),
+            topic: "output_1".into(),
+            headers,
+            payload: b"{}".to_vec(),
+        };
+
+        let record = to_dead_letter(record, "output_1_dlt", FailureReason::Delivery, "Message timed out");
+        assert_eq!(record.topic, "output_1_dlt");
+        assert_eq!(record.payload, b"{}");
+        assert_eq!(header(&record, HEADER_VERSION), "v7.2.1");
+        assert_eq!(header(&record, HEADER_FAILURE_REASON), "delivery");
+        assert_eq!(header(&record, HEADER_FAILURE_DETAIL), "Message timed out");
+        assert_eq!(header(&record, HEADER_ORIGINAL_TOPIC), "output_1");
+        assert!(chrono::DateTime::parse_from_rfc3339(header(&record, HEADER_RECEIVED_AT)).is_ok());
+    }
+
+    #[test]
+    fn test_validation_failure() {
+        let record = ProducerRecord {
+            key: "test".into(),
+            topic: String::new(),
+            headers: HashMap::new(),
+            payload: b"{}".to_vec(),
+        };
+
+        let record = to_dead_letter(record, "output_1_dlt", FailureReason::Validation, "SVC0002");
+        assert_eq!(header(&record, HEADER_FAILURE_REASON), "validation");
+        assert!(!record.headers.contains_key(HEADER_ORIGINAL_TOPIC));
+    }
+}
@@ -0,0 +1,171 @@
+//! Deduplication of the events resent by retrying clients.
+//!
+//! An event is a duplicate if an event with the same `sourceName`, `eventId` and `sequence` was
+//! accepted within `dedup.ttl_ms`. Duplicates are counted and not forwarded, or sent to the
+//! dead-letter topic with `dedup.dead_letter`. The window holds at most `dedup.max_entries`
+//! events, the oldest ones are evicted first. Events without these header fields are always
+//! forwarded.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::{event::EventHeader, metrics::MetricsText, settings::DedupSettings};
+
+#[derive(Clone, Hash, PartialEq, Eq)]
+struct Key {
+    source_name: String,
+    event_id: String,
+    sequence: i64,
+}
+
+impl Key {
+    fn new(header: &EventHeader) -> Option<Self> {
+        Some(Self {
+            source_name: header.source_name.clone()?,
+            event_id: header.event_id.clone()?,
+            sequence: header.sequence?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Window {
+    seen: HashMap<Key, Instant>,
+    /// The keys of `seen` in the order of arrival
+    order: VecDeque<(Key, Instant)>,
+}
+
+#[derive(Default, Debug)]
+pub struct DedupMetrics {
+    /// The number of duplicates found
+    pub duplicates: AtomicU64,
+    /// The number of events removed from the window before their TTL because it was full
+    pub evicted: AtomicU64,
+}
+
+pub struct Deduplicator {
+    ttl: Duration,
+    max_entries: usize,
+    dead_letter: bool,
+    window: Mutex<Window>,
+    metrics: DedupMetrics,
+}
+
+impl Deduplicator {
+    pub fn new(settings: &DedupSettings) -> Self {
+        Self {
+            ttl: Duration::from_millis(settings.ttl_ms),
+            max_entries: settings.max_entries,
+            dead_letter: settings.dead_letter,
+            window: Mutex::new(Window::default()),
+            metrics: DedupMetrics::default(),
+        }
+    }
+
+    /// Whether the duplicates go to the dead-letter topic.
+    pub fn dead_letter(&self) -> bool {
+        self.dead_letter
+    }
+
+    /// Checks the event and adds it to the window if it is not a duplicate.
+    pub fn is_duplicate(&self, header: &EventHeader, now: Instant) -> bool {
+        let Some(key) = Key::new(header) else {
+            return false;
+        };
+
+        let mut window = self.window.lock().unwrap();
+        while window.order.front().is_some_and(|(_, time)| now.duration_since(*time) >= self.ttl) {
+            let (expired, _) = window.order.pop_front().expect("Checked above");
+            window.seen.remove(&expired);
+        }
+
+        if window.seen.contains_key(&key) {
+            self.metrics.duplicates.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+
+        window.seen.insert(key.clone(), now);
+        window.order.push_back((key, now));
+        while window.order.len() > self.max_entries {
+            let (evicted, _) = window.order.pop_front().expect("Not empty");
+            window.seen.remove(&evicted);
+            self.metrics.evicted.fetch_add(1, Ordering::Relaxed);
+        }
+        false
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "entries": self.window.lock().unwrap().order.len(),
+            "duplicates": self.metrics.duplicates.load(Ordering::Relaxed),
+            "evicted": self.metrics.evicted.load(Ordering::Relaxed),
+        })
+    }
+
+    pub fn write_metrics(&self, metrics: &mut MetricsText) {
+        let entries = self.window.lock().unwrap().order.len() as u64;
+        metrics.gauge("ves_dedup_entries", "Events in the deduplication window", entries);
+        metrics.counter("ves_dedup_duplicates_total", "Duplicate events found", self.metrics.duplicates.load(Ordering::Relaxed));
+        metrics.counter(
+            "ves_dedup_evicted_total",
+            "Events removed from the full deduplication window before their TTL",
+            self.metrics.evicted.load(Ordering::Relaxed),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(source_name: &str, event_id: &str, sequence: i64) -> EventHeader {
+        EventHeader::new(&json!({
+            "commonEventHeader": { "sourceName": source_name, "eventId": event_id, "sequence": sequence, "domain": "fault" },
+        }))
+    }
+
+    fn deduplicator(ttl_ms: u64, max_entries: usize) -> Deduplicator {
+        Deduplicator::new(&DedupSettings { enable: true, ttl_ms, max_entries, dead_letter: false })
+    }
+
+    #[test]
+    fn test_duplicates_within_ttl() {
+        let dedup = deduplicator(1000, 10);
+        let start = Instant::now();
+
+        assert!(!dedup.is_duplicate(&event("a", "1", 1), start));
+        assert!(!dedup.is_duplicate(&event("a", "1", 2), start));
+        assert!(!dedup.is_duplicate(&event("b", "1", 1), start));
+        assert!(dedup.is_duplicate(&event("a", "1", 1), start + Duration::from_millis(999)));
+        assert!(!dedup.is_duplicate(&event("a", "1", 1), start + Duration::from_millis(1000)));
+        assert!(!dedup.is_duplicate(&EventHeader::default(), start));
+        assert!(!dedup.is_duplicate(&EventHeader::default(), start));
+
+        assert_eq!(dedup.to_json(), json!({ "entries": 1, "duplicates": 1, "evicted": 0 }));
+    }
+
+    #[test]
+    fn test_eviction() {
+        let dedup = deduplicator(60_000, 2);
+        let now = Instant::now();
+
+        for id in ["1", "2", "3"] {
+            assert!(!dedup.is_duplicate(&event("a", id, 1), now));
+        }
+        assert!(!dedup.is_duplicate(&event("a", "1", 1), now));
+        assert!(dedup.is_duplicate(&event("a", "3", 1), now));
+        assert_eq!(dedup.metrics.evicted.load(Ordering::Relaxed), 2);
+
+        let mut metrics = MetricsText::default();
+        dedup.write_metrics(&mut metrics);
+        assert!(metrics.into_string().contains("\nves_dedup_evicted_total 2\n"));
+    }
+}
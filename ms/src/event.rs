@@ -0,0 +1,56 @@
+//! The fields of an event used by the collector itself.
+//!
+//! They are read once when the request is parsed, see [`crate::http_server::parse_events`], and
+//! passed on to the rate limiting, the deduplication and the heartbeat supervision. Every field
+//! is optional: a missing or mistyped one only disables the features which need it for that
+//! event, the schema validation decides whether the event is accepted.
+
+use serde_json::Value;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EventHeader {
+    pub domain: Option<String>,
+    pub event_id: Option<String>,
+    pub source_name: Option<String>,
+    pub reporting_entity_name: Option<String>,
+    pub sequence: Option<i64>,
+    /// `heartbeatFields.heartbeatInterval` in seconds, if it is a positive integer
+    pub heartbeat_interval: Option<u64>,
+}
+
+impl EventHeader {
+    pub fn new(event: &Value) -> Self {
+        let header = &event["commonEventHeader"];
+        let string = |key: &str| header[key].as_str().map(str::to_string);
+
+        Self {
+            domain: string("domain"),
+            event_id: string("eventId"),
+            source_name: string("sourceName"),
+            reporting_entity_name: string("reportingEntityName"),
+            sequence: header["sequence"].as_i64(),
+            heartbeat_interval: event["heartbeatFields"]["heartbeatInterval"].as_u64().filter(|x| *x > 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let event = json!({
+            "commonEventHeader": { "domain": "heartbeat", "sourceName": "a", "sequence": 3, "eventId": 7 },
+            "heartbeatFields": { "heartbeatInterval": -1 },
+        });
+
+        assert_eq!(
+            EventHeader::new(&event),
+            EventHeader { domain: Some("heartbeat".into()), source_name: Some("a".into()), sequence: Some(3), ..Default::default() }
+        );
+        assert_eq!(EventHeader::new(&json!(1)), EventHeader::default());
+    }
+}
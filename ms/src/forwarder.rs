@@ -0,0 +1,270 @@
+//! Forwarding of the queued records to Kafka.
+//!
+//! The records queued at the same time are sent as a batch. If the producer queue is full, the
+//! `kafka.queue_full` policy applies before the records fall back, but the whole batch waits at
+//! most `kafka.queue_full.batch_timeout_ms`.
+//!
+//! Records which cannot be sent go to the [`Spool`] if it is enabled and the failure is
+//! temporary, i.e., the producer queue is full or the broker is unreachable. Otherwise they go to
+//! the dead-letter topic, see [`crate::dead_letter`].
+//!
+//! While the spool is not empty, new records are spooled too, so they are sent in the order of
+//! arrival once the broker is available again. A record is removed from the spool when it is
+//! handed over to the producer; if its delivery fails again, it is spooled again at the end.
+//!
+//! The delivery failures are reported on the polling thread of the producer, which must not
+//! block, so they are passed to the forwarder thread and handled there.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender},
+        Arc,
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use rdkafka::{error::KafkaError, types::RDKafkaErrorCode};
+
+use ers::kafka::{FailedRecord, KafkaProducer, ProducerRecord, SendError};
+
+use crate::{
+    dead_letter::{to_dead_letter, FailureReason},
+    spool::Spool,
+};
+
+/// How long to wait for a new record before replaying the spool.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The maximum number of records replayed between two new records.
+const REPLAY_BATCH: usize = 1000;
+
+/// The maximum number of queued records sent at once.
+const SEND_BATCH: usize = 1000;
+
+/// Whether the error means that Kafka is temporarily unavailable.
+fn is_temporary(err: &KafkaError) -> bool {
+    matches!(
+        err.rdkafka_error_code(),
+        Some(
+            RDKafkaErrorCode::QueueFull
+                | RDKafkaErrorCode::MessageTimedOut
+                | RDKafkaErrorCode::AllBrokersDown
+                | RDKafkaErrorCode::BrokerTransportFailure
+                | RDKafkaErrorCode::RequestTimedOut
+                | RDKafkaErrorCode::NetworkException
+        )
+    )
+}
+
+/// Where the records go when they cannot be sent.
+pub struct Fallback {
+    tx: Sender<ProducerRecord>,
+    failures: Sender<FailedRecord>,
+    dead_letter_topic: Option<String>,
+    spool: Option<Mutex<Spool>>,
+    available: AtomicBool,
+}
+
+impl Fallback {
+    /// `tx` is the queue of the [`Forwarder`], the dead-letter records are sent through it.
+    /// `failures` is its queue of the undelivered records.
+    pub fn new(
+        tx: Sender<ProducerRecord>,
+        failures: Sender<FailedRecord>,
+        dead_letter_topic: Option<String>,
+        spool: Option<Spool>,
+    ) -> Self {
+        Self {
+            tx,
+            failures,
+            dead_letter_topic,
+            spool: spool.map(Mutex::new),
+            available: AtomicBool::new(true),
+        }
+    }
+
+    /// Passes a record which could not be delivered to the [`Forwarder`], see
+    /// [`ers::kafka::KafkaProducerBuilder::on_delivery_failure`].
+    pub fn on_delivery_failure(&self, failed: FailedRecord) {
+        if let Err(err) = self.failures.send(failed) {
+            log::error!("Message to topic `{}` is lost: forwarder stopped", err.0.record.topic);
+        }
+    }
+
+    fn on_failure(&self, record: ProducerRecord, err: &SendError) {
+        let temporary = err.kafka_error().is_some_and(is_temporary);
+        if self.spool.is_some() && temporary && !self.is_dead_letter(&record) {
+            self.available.store(false, Ordering::Relaxed);
+            self.spool(record, &err.to_string());
+        } else {
+            self.dead_letter(record, &err.to_string());
+        }
+    }
+
+    fn is_dead_letter(&self, record: &ProducerRecord) -> bool {
+        self.dead_letter_topic.as_ref().is_some_and(|topic| *topic == record.topic)
+    }
+
+    /// Writes the record to the spool, or to the dead-letter topic if the spool is full.
+    fn spool(&self, record: ProducerRecord, reason: &str) {
+        let result = match &self.spool {
+            Some(spool) => spool.lock().unwrap().push(&record),
+            None => return self.dead_letter(record, reason),
+        };
+
+        if let Err(err) = result {
+            log::warn!("Failed to spool message: {}", err);
+            self.dead_letter(record, reason);
+        }
+    }
+
+    /// Queues the record to the dead-letter topic, unless it was sent there.
+    fn dead_letter(&self, record: ProducerRecord, reason: &str) {
+        match &self.dead_letter_topic {
+            Some(topic) if !self.is_dead_letter(&record) => {
+                let _ = self.tx.send(to_dead_letter(record, topic, FailureReason::Delivery, reason));
+            }
+            _ => log::error!("Message to topic `{}` is lost: {}", record.topic, reason),
+        }
+    }
+}
+
+pub struct Forwarder {
+    producer: Arc<KafkaProducer>,
+    fallback: Arc<Fallback>,
+    /// The undelivered records, see [`Fallback::on_delivery_failure`]
+    failures: Receiver<FailedRecord>,
+    /// Time between two attempts to send while Kafka is unavailable
+    retry_interval: Duration,
+    /// How long a batch may wait for room in the producer queue
+    batch_timeout: Duration,
+    was_available: bool,
+    last_probe: Instant,
+    /// Delivered messages at the last probe, more means Kafka is available again
+    sent_at_probe: u64,
+}
+
+impl Forwarder {
+    pub fn new(
+        producer: Arc<KafkaProducer>,
+        fallback: Arc<Fallback>,
+        failures: Receiver<FailedRecord>,
+        retry_interval: Duration,
+        batch_timeout: Duration,
+    ) -> Self {
+        Self {
+            producer,
+            fallback,
+            failures,
+            retry_interval,
+            batch_timeout,
+            was_available: true,
+            last_probe: Instant::now(),
+            sent_at_probe: 0,
+        }
+    }
+
+    /// Sends the records received on `rx` until the channel is closed, the ones already queued
+    /// together.
+    pub fn run(mut self, rx: Receiver<ProducerRecord>) {
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(record) => {
+                    let mut records = vec![record];
+                    records.extend(rx.try_iter().take(SEND_BATCH - 1));
+                    self.forward(records);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    log::info!("Channel closed");
+                    return;
+                }
+            }
+            for failed in self.failures.try_iter() {
+                self.fallback.on_failure(failed.record, &SendError::Kafka(failed.error));
+            }
+            self.replay();
+        }
+    }
+
+    fn forward(&self, mut records: Vec<ProducerRecord>) {
+        let spooling = self.fallback.spool.as_ref().is_some_and(|spool| {
+            !self.fallback.available.load(Ordering::Relaxed) || !spool.lock().unwrap().is_empty()
+        });
+
+        if spooling {
+            let (dead_letters, spooled) = records.into_iter().partition(|x| self.fallback.is_dead_letter(x));
+            for record in spooled {
+                self.fallback.spool(record, "Kafka is unavailable");
+            }
+            records = dead_letters;
+        }
+
+        let results = self.producer.send_batch(&records, Some(Instant::now() + self.batch_timeout));
+        let sent = results.iter().filter(|x| x.is_ok()).count();
+        if sent > 0 {
+            log::info!("{} messages sent to Kafka broker", sent);
+        }
+
+        for (record, result) in records.into_iter().zip(results) {
+            if let Err(err) = result {
+                log::warn!("Failed to send message to Kafka broker: {}", err);
+                self.fallback.on_failure(record, &err);
+            }
+        }
+    }
+
+    /// Sends the spooled records while Kafka is available, or a single one as a probe once in
+    /// every retry interval.
+    fn replay(&mut self) {
+        let Some(spool) = &self.fallback.spool else {
+            return;
+        };
+
+        let sent = self.producer.metrics().sent_messages.load(Ordering::Relaxed);
+        let available = self.fallback.available.load(Ordering::Relaxed);
+        if self.was_available && !available {
+            log::warn!("Kafka is unavailable, spooling messages");
+            self.last_probe = Instant::now();
+            self.sent_at_probe = sent;
+        }
+        self.was_available = available;
+
+        let batch = if available {
+            REPLAY_BATCH
+        } else if sent > self.sent_at_probe {
+            log::info!("Kafka is available again, replaying the spool");
+            self.fallback.available.store(true, Ordering::Relaxed);
+            self.was_available = true;
+            REPLAY_BATCH
+        } else if self.last_probe.elapsed() >= self.retry_interval {
+            self.last_probe = Instant::now();
+            1
+        } else {
+            return;
+        };
+
+        // The spool is not locked while sending: the producer may poll the delivery reports,
+        // whose failures are spooled again.
+        for _ in 0..batch {
+            let Some(record) = spool.lock().unwrap().front() else {
+                break;
+            };
+
+            match self.producer.send(&record) {
+                Ok(_) => {
+                    let mut spool = spool.lock().unwrap();
+                    spool.pop_front();
+                    spool.metrics().replayed_records.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(err) if err.kafka_error().is_some_and(is_temporary) => break,
+                Err(err) => {
+                    spool.lock().unwrap().pop_front();
+                    self.fallback.dead_letter(record, &err.to_string());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Generator of VES 7.2 events, e.g., for load tests.
+//!
+//! The events of every [`Domain`] have the required fields of `CommonEventFormat_30.2.1` and a
+//! few realistic optional ones, with random values where the schema allows it.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rand::{seq::SliceRandom, Rng};
+use serde_json::{json, Value};
+
+/// The event domains the generator supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Domain {
+    Fault,
+    Heartbeat,
+    Measurement,
+    PnfRegistration,
+    Notification,
+    StndDefined,
+}
+
+impl Domain {
+    pub const ALL: [Domain; 6] = [
+        Domain::Fault,
+        Domain::Heartbeat,
+        Domain::Measurement,
+        Domain::PnfRegistration,
+        Domain::Notification,
+        Domain::StndDefined,
+    ];
+
+    /// The value of `commonEventHeader.domain`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Domain::Fault => "fault",
+            Domain::Heartbeat => "heartbeat",
+            Domain::Measurement => "measurement",
+            Domain::PnfRegistration => "pnfRegistration",
+            Domain::Notification => "notification",
+            Domain::StndDefined => "stndDefined",
+        }
+    }
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Domain {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Domain::ALL
+            .into_iter()
+            .find(|x| x.name() == value)
+            .ok_or_else(|| format!("unknown domain: {}", value))
+    }
+}
+
+const SEVERITIES: [&str; 5] = ["CRITICAL", "MAJOR", "MINOR", "WARNING", "NORMAL"];
+const ALARMS: [(&str, &str); 4] = [
+    ("linkDown", "Link is down"),
+    ("powerSupplyFailure", "Power supply failure"),
+    ("highTemperature", "Temperature above threshold"),
+    ("fanFailure", "Fan failure"),
+];
+
+/// Generates the events of one source with increasing sequence numbers.
+pub struct EventGenerator {
+    source_name: String,
+    sequence: u64,
+}
+
+impl EventGenerator {
+    pub fn new(source_name: impl Into<String>) -> Self {
+        Self { source_name: source_name.into(), sequence: 0 }
+    }
+
+    /// A single event of the domain, without the `event` root key.
+    pub fn event(&mut self, domain: Domain) -> Value {
+        let mut rng = rand::thread_rng();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        self.sequence += 1;
+
+        let header = json!({
+            "domain": domain.name(),
+            "eventId": format!("{}-{}-{}", domain, self.source_name, self.sequence),
+            "eventName": format!("{}_{}", domain, self.source_name),
+            "lastEpochMicrosec": now,
+            "priority": if domain == Domain::Fault { "High" } else { "Normal" },
+            "reportingEntityName": self.source_name,
+            "sequence": self.sequence,
+            "sourceName": self.source_name,
+            "startEpochMicrosec": now - 60_000_000,
+            "nfVendorName": "Ericsson",
+            "timeZoneOffset": "UTC+00:00",
+            "version": "4.0.1",
+            "vesEventListenerVersion": "7.2.1",
+        });
+        let mut event = json!({ "commonEventHeader": header });
+
+        let (key, fields) = match domain {
+            Domain::Fault => {
+                let (condition, problem) = ALARMS.choose(&mut rng).unwrap();
+                ("faultFields", json!({
+                    "alarmCondition": condition,
+                    "eventSeverity": SEVERITIES.choose(&mut rng).unwrap(),
+                    "eventSourceType": "other",
+                    "faultFieldsVersion": "4.0",
+                    "specificProblem": problem,
+                    "vfStatus": "Active",
+                    "alarmInterfaceA": format!("eth{}", rng.gen_range(0..4)),
+                }))
+            }
+            Domain::Heartbeat => ("heartbeatFields", json!({
+                "heartbeatFieldsVersion": "3.0",
+                "heartbeatInterval": 60,
+            })),
+            Domain::Measurement => ("measurementFields", json!({
+                "measurementFieldsVersion": "4.0",
+                "measurementInterval": 60,
+                "cpuUsageArray": (0..2).map(|i| json!({
+                    "cpuIdentifier": format!("cpu{}", i),
+                    "percentUsage": rng.gen_range(0.0..100.0),
+                })).collect::<Vec<_>>(),
+                "memoryUsageArray": [{
+                    "vmIdentifier": self.source_name,
+                    "memoryFree": rng.gen_range(0..4_000_000),
+                    "memoryUsed": rng.gen_range(0..4_000_000),
+                }],
+            })),
+            Domain::PnfRegistration => ("pnfRegistrationFields", json!({
+                "pnfRegistrationFieldsVersion": "2.1",
+                "oamV4IpAddress": format!("10.0.{}.{}", rng.gen_range(0..256), rng.gen_range(1..255)),
+                "serialNumber": format!("SN{:08}", rng.gen_range(0..100_000_000)),
+                "vendorName": "Ericsson",
+            })),
+            Domain::Notification => ("notificationFields", json!({
+                "changeIdentifier": format!("change-{}", self.sequence),
+                "changeType": "configurationChanged",
+                "notificationFieldsVersion": "2.0",
+                "newState": "inService",
+                "oldState": "outOfService",
+            })),
+            Domain::StndDefined => {
+                event["commonEventHeader"]["stndDefinedNamespace"] = json!("3GPP-FaultSupervision");
+                ("stndDefinedFields", json!({
+                    "schemaReference": "https://forge.3gpp.org/rep/sa5/MnS/blob/Rel-16/OpenAPI/faultMnS.yaml#components/schemas/NotifyNewAlarm",
+                    "data": {
+                        "href": format!("href-{}", self.sequence),
+                        "notificationId": self.sequence,
+                        "notificationType": "notifyNewAlarm",
+                        "eventTime": chrono::Utc::now().to_rfc3339(),
+                        "perceivedSeverity": SEVERITIES.choose(&mut rng).unwrap(),
+                    },
+                    "stndDefinedFieldsVersion": "1.0",
+                }))
+            }
+        };
+        event[key] = fields;
+        event
+    }
+
+    /// A request body with a single event of a random domain of `domains`.
+    pub fn request(&mut self, domains: &[Domain]) -> Value {
+        let domain = *domains.choose(&mut rand::thread_rng()).expect("No domains");
+        json!({ "event": self.event(domain) })
+    }
+
+    /// A batch request body with `size` events, all of the same random domain of `domains`,
+    /// because a batch must not mix domains.
+    pub fn batch(&mut self, domains: &[Domain], size: usize) -> Value {
+        let domain = *domains.choose(&mut rand::thread_rng()).expect("No domains");
+        json!({ "eventList": (0..size).map(|_| self.event(domain)).collect::<Vec<_>>() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonschema::JSONSchema;
+
+    use crate::http_server::{parse_events, validate_event};
+
+    use super::*;
+
+    fn schema() -> JSONSchema {
+        let x = serde_json::from_str(include_str!("../res/schemas/CommonEventFormat_30.2.1.json")).expect("Invalid JSON");
+        JSONSchema::compile(&x).expect("Invalid JSON schema")
+    }
+
+    #[test]
+    fn test_events_are_valid() {
+        let schema = schema();
+        let mut generator = EventGenerator::new("node1");
+        for domain in Domain::ALL {
+            let body = json!({ "event": generator.event(domain) }).to_string();
+            let events = parse_events(body.as_bytes(), "event").unwrap();
+            assert!(validate_event(&events, &schema), "invalid {} event: {}", domain, body);
+        }
+
+        let body = generator.batch(&Domain::ALL, 3).to_string();
+        let events = parse_events(body.as_bytes(), "eventList").unwrap();
+        assert!(validate_event(&events, &schema));
+        assert_eq!(events.events().len(), 3);
+    }
+
+    #[test]
+    fn test_domain_from_str() {
+        assert_eq!("pnfRegistration".parse(), Ok(Domain::PnfRegistration));
+        assert!("syslog".parse::<Domain>().is_err());
+    }
+}
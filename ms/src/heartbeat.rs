@@ -0,0 +1,277 @@
+//! Supervision of the heartbeats of the VES sources.
+//!
+//! The last heartbeat of every `sourceName` is tracked with its `heartbeatInterval`. When a
+//! source misses `heartbeat.missed_intervals` intervals in a row, a synthetic VES 7.2.1 fault
+//! event is produced to the output topic. The next heartbeat of the source clears it with a fault
+//! event of `NORMAL` severity and the same `eventId`.
+//!
+//! The intervals are capped by `heartbeat.max_interval_s` and the number of sources by
+//! `heartbeat.max_sources`, so that the sources cannot exhaust the memory or overflow the
+//! deadlines.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use ers::{
+    kafka::{ProducerRecord, Registered, Serializer},
+    SemVer,
+};
+
+use crate::{dead_letter::set_origin, event::EventHeader, settings::HeartbeatSettings};
+
+/// The version of the synthetic fault events, in the `ves-version` header and in the registry
+const FAULT_VERSION: &str = "v7.2.1";
+const FAULT_SCHEMA: SemVer = SemVer { major: 7, minor: 2, patch: 1 };
+
+const ALARM_CONDITION: &str = "heartbeatMissed";
+
+struct Source {
+    last_heartbeat: DateTime<Utc>,
+    interval: Duration,
+    alarm: bool,
+}
+
+/// The heartbeat state of a source, reported by `GET /heartbeats`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceStatus {
+    pub source_name: String,
+    pub last_heartbeat: String,
+    /// In seconds
+    pub heartbeat_interval: u64,
+    /// Whether the fault of the missed heartbeats is raised
+    pub alarm: bool,
+}
+
+pub struct HeartbeatMonitor {
+    missed_intervals: u32,
+    default_interval: Duration,
+    max_interval: Duration,
+    max_sources: usize,
+    reporting_entity_name: String,
+    sources: Mutex<HashMap<String, Source>>,
+    sequence: AtomicU64,
+}
+
+impl HeartbeatMonitor {
+    pub fn new(settings: &HeartbeatSettings) -> Self {
+        Self {
+            missed_intervals: settings.missed_intervals,
+            default_interval: Duration::from_secs(settings.default_interval_s),
+            max_interval: Duration::from_secs(settings.max_interval_s),
+            max_sources: settings.max_sources,
+            reporting_entity_name: settings.reporting_entity_name.clone(),
+            sources: Mutex::new(HashMap::new()),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Records the event if it is a heartbeat. Returns the event clearing the fault if the source
+    /// had missed its heartbeats.
+    pub fn observe(&self, header: &EventHeader, now: DateTime<Utc>) -> Option<Value> {
+        if header.domain.as_deref() != Some("heartbeat") {
+            return None;
+        }
+
+        let name = header.source_name.clone()?;
+        let interval = header
+            .heartbeat_interval
+            .map_or(self.default_interval, Duration::from_secs)
+            .min(self.max_interval);
+
+        let mut sources = self.sources.lock().unwrap();
+        if !sources.contains_key(&name) && sources.len() >= self.max_sources {
+            Self::evict(&mut sources);
+        }
+        let previous = sources.insert(name.clone(), Source { last_heartbeat: now, interval, alarm: false });
+        if !previous.is_some_and(|x| x.alarm) {
+            return None;
+        }
+
+        log::info!("Heartbeats of `{}` resumed", name);
+        Some(self.fault(&name, "NORMAL", "Heartbeats resumed", now))
+    }
+
+    /// Raises the fault of the sources which have missed their heartbeats since the last check.
+    pub fn check(&self, now: DateTime<Utc>) -> Vec<Value> {
+        let mut sources = self.sources.lock().unwrap();
+        let mut faults = Vec::new();
+        for (name, source) in sources.iter_mut().filter(|(_, x)| !x.alarm) {
+            let deadline = source.interval.checked_mul(self.missed_intervals).unwrap_or(Duration::MAX);
+            if (now - source.last_heartbeat).to_std().is_ok_and(|x| x >= deadline) {
+                log::warn!("`{}` missed {} heartbeats", name, self.missed_intervals);
+                source.alarm = true;
+                let problem = format!("{} heartbeats missed", self.missed_intervals);
+                faults.push(self.fault(name, "MAJOR", &problem, now));
+            }
+        }
+        faults
+    }
+
+    /// Drops the source with the oldest heartbeat.
+    fn evict(sources: &mut HashMap<String, Source>) {
+        let oldest = sources.iter().min_by_key(|(_, x)| x.last_heartbeat).map(|(name, _)| name.clone());
+        if let Some(name) = oldest {
+            log::warn!("Too many sources, no longer supervising `{}`", name);
+            sources.remove(&name);
+        }
+    }
+
+    /// The sources sorted by name.
+    pub fn status(&self) -> Vec<SourceStatus> {
+        let mut status = self
+            .sources
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, source)| SourceStatus {
+                source_name: name.clone(),
+                last_heartbeat: source.last_heartbeat.to_rfc3339_opts(SecondsFormat::Millis, true),
+                heartbeat_interval: source.interval.as_secs(),
+                alarm: source.alarm,
+            })
+            .collect::<Vec<_>>();
+        status.sort_by(|a, b| a.source_name.cmp(&b.source_name));
+        status
+    }
+
+    fn fault(&self, source_name: &str, severity: &str, problem: &str, now: DateTime<Utc>) -> Value {
+        let epoch = now.timestamp_micros();
+        json!({
+            "commonEventHeader": {
+                "domain": "fault",
+                "eventId": format!("{}-{}", ALARM_CONDITION, source_name),
+                "eventName": format!("Fault_{}", ALARM_CONDITION),
+                "lastEpochMicrosec": epoch,
+                "priority": "High",
+                "reportingEntityName": self.reporting_entity_name,
+                "sequence": self.sequence.fetch_add(1, Ordering::Relaxed),
+                "sourceName": source_name,
+                "startEpochMicrosec": epoch,
+                "version": "4.0.1",
+                "vesEventListenerVersion": "7.2.1",
+            },
+            "faultFields": {
+                "alarmCondition": ALARM_CONDITION,
+                "eventSeverity": severity,
+                "eventSourceType": "other",
+                "faultFieldsVersion": "4.0",
+                "specificProblem": problem,
+                "vfStatus": "Active",
+            },
+        })
+    }
+}
+
+/// Creates the record of a synthetic fault event, in the schema registry wire format if its
+/// schema is registered, see [`crate::types::AppState::schema_ids`].
+pub fn fault_record(topic: &str, event: &Value, schema_ids: &HashMap<SemVer, u32>) -> ProducerRecord {
+    let mut headers = HashMap::new();
+    set_origin(&mut headers, FAULT_VERSION);
+
+    let value = event.to_string().into_bytes();
+    let payload = match schema_ids.get(&FAULT_SCHEMA).copied() {
+        Some(schema_id) => Registered { schema_id, value: value.as_slice() }
+            .serialize()
+            .expect("Bytes are always serializable")
+            .into_owned(),
+        None => value,
+    };
+
+    ProducerRecord {
+        key: event["commonEventHeader"]["sourceName"].as_str().unwrap_or_default().into(),
+        topic: topic.to_owned(),
+        headers,
+        payload,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonschema::JSONSchema;
+
+    use super::*;
+
+    fn heartbeat(source_name: &str, interval: u64) -> EventHeader {
+        EventHeader::new(&json!({
+            "commonEventHeader": { "domain": "heartbeat", "sourceName": source_name },
+            "heartbeatFields": { "heartbeatFieldsVersion": "3.0", "heartbeatInterval": interval },
+        }))
+    }
+
+    #[test]
+    fn test_missed_and_resumed() {
+        let monitor = HeartbeatMonitor::new(&HeartbeatSettings::default());
+        let start = Utc::now();
+        let at = |seconds| start + chrono::Duration::seconds(seconds);
+
+        assert_eq!(monitor.observe(&heartbeat("a", 10), start), None);
+        assert_eq!(monitor.observe(&heartbeat("b", 60), start), None);
+        assert_eq!(monitor.observe(&EventHeader::new(&json!({"commonEventHeader": {"domain": "fault", "sourceName": "c"}})), start), None);
+
+        assert!(monitor.check(at(29)).is_empty());
+        let faults = monitor.check(at(30));
+        assert_eq!(faults.len(), 1);
+        assert_eq!(faults[0]["commonEventHeader"]["sourceName"], "a");
+        assert_eq!(faults[0]["faultFields"]["eventSeverity"], "MAJOR");
+        assert!(monitor.check(at(40)).is_empty());
+        assert_eq!(monitor.status().iter().map(|x| x.alarm).collect::<Vec<_>>(), [true, false]);
+
+        let clear = monitor.observe(&heartbeat("a", 10), at(45)).unwrap();
+        assert_eq!(clear["faultFields"]["eventSeverity"], "NORMAL");
+        assert_eq!(clear["commonEventHeader"]["eventId"], faults[0]["commonEventHeader"]["eventId"]);
+        assert!(!monitor.status()[0].alarm);
+    }
+
+    #[test]
+    fn test_interval_limits() {
+        let settings = HeartbeatSettings { max_interval_s: u64::MAX, ..Default::default() };
+        let monitor = HeartbeatMonitor::new(&settings);
+        let start = Utc::now();
+        monitor.observe(&heartbeat("a", u64::MAX), start);
+        assert!(monitor.check(start + chrono::Duration::days(365)).is_empty());
+
+        let monitor = HeartbeatMonitor::new(&HeartbeatSettings::default());
+        monitor.observe(&heartbeat("a", u64::MAX), start);
+        let negative = EventHeader::new(&json!({
+            "commonEventHeader": { "domain": "heartbeat", "sourceName": "b" },
+            "heartbeatFields": { "heartbeatInterval": -10 },
+        }));
+        monitor.observe(&negative, start);
+        assert_eq!(monitor.status().iter().map(|x| x.heartbeat_interval).collect::<Vec<_>>(), [86400, 60]);
+    }
+
+    #[test]
+    fn test_max_sources() {
+        let monitor = HeartbeatMonitor::new(&HeartbeatSettings { max_sources: 2, ..Default::default() });
+        let start = Utc::now();
+        monitor.observe(&heartbeat("a", 10), start);
+        monitor.observe(&heartbeat("b", 10), start + chrono::Duration::seconds(1));
+        monitor.observe(&heartbeat("a", 10), start + chrono::Duration::seconds(2));
+        monitor.observe(&heartbeat("c", 10), start + chrono::Duration::seconds(3));
+        assert_eq!(monitor.status().iter().map(|x| x.source_name.as_str()).collect::<Vec<_>>(), ["a", "c"]);
+    }
+
+    #[test]
+    fn test_fault_is_valid() {
+        let schema = serde_json::from_str(include_str!("../res/schemas/CommonEventFormat_30.2.1.json")).unwrap();
+        let schema = JSONSchema::compile(&schema).unwrap();
+        let monitor = HeartbeatMonitor::new(&HeartbeatSettings::default());
+        let fault = monitor.fault("a", "MAJOR", "3 heartbeats missed", Utc::now());
+        assert!(schema.is_valid(&json!({ "event": fault })));
+
+        let record = fault_record("output_1", &fault, &HashMap::new());
+        assert_eq!(record.key, b"a");
+        assert_eq!(serde_json::from_slice::<Value>(&record.payload).unwrap(), fault);
+    }
+}
@@ -1,62 +1,90 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     sync::{
         Arc,
         Mutex
     },
-    str
+    str,
+    time::Instant
 };
 
 use actix_web::{
     get,
     post,
+    put,
     web,
     App,
     HttpRequest,
     HttpResponse,
+    HttpResponseBuilder,
     HttpServer,
     Responder,
+    http::StatusCode,
     http::header::{
         AsHeaderName,
         ContentType,
         HeaderMap,
-        ToStrError
+        HeaderValue,
+        ToStrError,
+        AUTHORIZATION,
+        RETRY_AFTER
     }
 };
+use base64::{prelude::BASE64_STANDARD, Engine};
 
+use chrono::Utc;
 use jsonschema::JSONSchema;
 use log::info;
-use serde_json::json;
+use opentelemetry::propagation::Extractor;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
 
 use ers::{
-    kafka::ProducerRecord,
+    kafka::{ProducerRecord, Registered, Serializer},
+    logging,
+    trace,
     SemVer
 };
 
 use crate::{
+    compression::{decode_body, HEADER_CONTENT_ENCODING},
+    dead_letter::{set_origin, to_dead_letter, FailureReason},
+    event::EventHeader,
+    heartbeat::fault_record,
+    rate_limit::RateLimitKey,
+    schemas::SchemaSource,
     types::AppState,
     ves::{
-        failed_schema_validation, invalid_api_version, VesError
+        failed_schema_validation, invalid_api_version, message_size_exceeded, too_many_requests, VesError
     },
 };
 
 const HEADER_MINOR_VERSION: &str = "X-MinorVersion";
 const HEADER_PATCH_VERSION: &str = "X-PatchVersion";
 const HEADER_LATEST_VERSION: &str = "X-LatestVersion";
-const HEADER_CONTENT_TYPE: &str = "Content-Type";
-const CONTENT_TYPE_JSON: &str = "application/json";
+/// The first major version with the version headers, see [`resolve_schema`]
+const VERSION_HEADERS_SINCE: u16 = 7;
 
 #[actix_web::main]
-pub async fn start(port: String, app_state: Arc<Mutex<AppState>>) {
-    let addr = "0.0.0.0:".to_owned() + &port;
+pub async fn start(port: u16, workers: usize, app_state: Arc<Mutex<AppState>>) {
+    let addr = format!("0.0.0.0:{}", port);
     let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(app_state.clone()))
             .service(health)
+            .service(get_log_level)
+            .service(set_log_level)
+            .service(spool_status)
+            .service(heartbeat_status)
+            .service(dedup_status)
+            .service(rate_limit_status)
+            .service(prometheus_metrics)
+            .service(list_schemas)
+            .service(get_schema)
             .service(process_event)
             .service(process_event_batch)
     })
-    .workers(1) // TODO(cfg): read from configuration
+    .workers(workers)
     .bind(&addr)
     .unwrap()
     .run();
@@ -70,24 +98,184 @@ async fn health() -> impl Responder {
     HttpResponse::Ok().body(String::from("Healthy\n"))
 }
 
+#[derive(Serialize, Deserialize)]
+struct LogLevel {
+    level: String,
+}
+
+fn log_level_error(err: logging::LoggingError) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type(ContentType::json())
+        .body(json!({ "error": err.to_string() }).to_string())
+}
+
+/// The active log filter in `RUST_LOG` syntax.
+#[get("/logLevel")]
+async fn get_log_level() -> impl Responder {
+    match logging::level() {
+        Ok(level) => HttpResponse::Ok().json(LogLevel { level }),
+        Err(err) => log_level_error(err),
+    }
+}
+
+/// Changes the log filter at runtime.
+///
+/// ```sh
+/// curl -X PUT localhost:8080/logLevel -H "Content-Type: application/json" -d '{"level": "info,ves=debug"}'
+/// ```
+#[put("/logLevel")]
+async fn set_log_level(body: web::Json<LogLevel>) -> impl Responder {
+    match logging::set_level(&body.level) {
+        Ok(()) => {
+            info!("Log level changed to `{}`", body.level);
+            HttpResponse::Ok().json(body.into_inner())
+        }
+        Err(err) => log_level_error(err),
+    }
+}
+
+/// Depth and counters of the local spool, 404 if it is disabled.
+#[get("/spool")]
+async fn spool_status(context: web::Data<Arc<Mutex<AppState>>>) -> impl Responder {
+    match &context.get_ref().lock().unwrap().spool_metrics {
+        Some(metrics) => HttpResponse::Ok().json(metrics.to_json()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// The last heartbeat of each source, 404 if the supervision is disabled.
+#[get("/heartbeats")]
+async fn heartbeat_status(context: web::Data<Arc<Mutex<AppState>>>) -> impl Responder {
+    match &context.get_ref().lock().unwrap().heartbeats {
+        Some(monitor) => HttpResponse::Ok().json(json!({ "sources": monitor.status() })),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Counters of the deduplication, 404 if it is disabled.
+#[get("/dedup")]
+async fn dedup_status(context: web::Data<Arc<Mutex<AppState>>>) -> impl Responder {
+    match &context.get_ref().lock().unwrap().dedup {
+        Some(dedup) => HttpResponse::Ok().json(dedup.to_json()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Counters of the rate limiting, 404 if it is disabled.
+#[get("/rateLimit")]
+async fn rate_limit_status(context: web::Data<Arc<Mutex<AppState>>>) -> impl Responder {
+    match &context.get_ref().lock().unwrap().rate_limiter {
+        Some(limiter) => HttpResponse::Ok().json(limiter.to_json()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// The metrics in the Prometheus text format, see [`crate::metrics`].
+#[get("/metrics")]
+async fn prometheus_metrics(context: web::Data<Arc<Mutex<AppState>>>) -> impl Responder {
+    let text = crate::metrics::render(&context.get_ref().lock().unwrap());
+    HttpResponse::Ok().content_type(crate::metrics::CONTENT_TYPE).body(text)
+}
+
+#[derive(Serialize)]
+struct SchemaEntry<'a> {
+    version: String,
+    #[serde(flatten)]
+    source: &'a SchemaSource,
+}
+
+/// The supported VES API versions with the file and the SHA-256 of their schemas.
+#[get("/schemas")]
+async fn list_schemas(context: web::Data<Arc<Mutex<AppState>>>) -> impl Responder {
+    let state = context.get_ref().lock().unwrap();
+    let schemas = state
+        .schema_sources
+        .iter()
+        .map(|(version, source)| SchemaEntry { version: version.to_string(), source })
+        .collect::<Vec<_>>();
+    HttpResponse::Ok().json(json!({ "schemas": schemas }))
+}
+
+/// The schema document of a version, e.g., `7.2.1` or `v7.2.1`.
+#[get("/schemas/{version}")]
+async fn get_schema(context: web::Data<Arc<Mutex<AppState>>>, path: web::Path<String>) -> impl Responder {
+    let state = context.get_ref().lock().unwrap();
+    let version = path.trim_start_matches('v').parse::<SemVer>();
+    match version.ok().and_then(|x| state.schema_sources.get(&x)) {
+        Some(source) => HttpResponse::Ok().content_type(ContentType::json()).body(source.definition.clone()),
+        None => HttpResponse::NotFound()
+            .content_type(ContentType::json())
+            .body(json!({ "error": format!("Unknown schema version: {}", path) }).to_string()),
+    }
+}
+
 #[post("/eventListener/{version}")]
 async fn process_event(
     req: HttpRequest,
     path: web::Path<String>,
-    data: web::Bytes,
+    payload: web::Payload,
     context: web::Data<Arc<Mutex<AppState>>>,
-) -> impl Responder {
-    return process_event_common(req, path, data, context, "event");
+) -> actix_web::Result<HttpResponse> {
+    Ok(match read_body(&req, payload, &context).await? {
+        Ok(data) => process_event_common(req, path, data, context, "event"),
+        Err(err) => request_error(err),
+    })
 }
 
 #[post("/eventListener/{version}/eventBatch")]
 async fn process_event_batch(
     req: HttpRequest,
     path: web::Path<String>,
-    data: web::Bytes,
+    payload: web::Payload,
     context: web::Data<Arc<Mutex<AppState>>>,
-) -> impl Responder {
-    return process_event_common(req, path, data, context, "eventList");
+) -> actix_web::Result<HttpResponse> {
+    let partial = context.get_ref().lock().unwrap().partial_batch;
+    Ok(match read_body(&req, payload, &context).await? {
+        Ok(data) if partial => process_event_batch_partial(req, path, data, context),
+        Ok(data) => process_event_common(req, path, data, context, "eventList"),
+        Err(err) => request_error(err),
+    })
+}
+
+/// Reads the request body and decodes it according to its `Content-Encoding`.
+///
+/// The message size policy applies to both the received and the decompressed body.
+async fn read_body(
+    req: &HttpRequest,
+    payload: web::Payload,
+    context: &web::Data<Arc<Mutex<AppState>>>,
+) -> actix_web::Result<Result<Vec<u8>, VesError>> {
+    let limit = context.get_ref().lock().unwrap().max_message_size;
+
+    let Ok(data) = payload.to_bytes_limited(limit).await else {
+        return Ok(Err(message_size_exceeded()));
+    };
+
+    let encoding = req.headers().get(HEADER_CONTENT_ENCODING).map(|x| x.to_str().unwrap_or("invalid"));
+    Ok(decode_body(encoding, data?.into(), limit))
+}
+
+fn request_error(err: VesError) -> HttpResponse {
+    HttpResponse::build(StatusCode::from_u16(err.status_code()).expect("Invalid status code"))
+        .content_type(ContentType::json())
+        .body(
+            serde_json::to_string(
+                &json!({ "requestError": err })
+            ).unwrap()
+        )
+}
+
+/// [`Extractor`] of the trace context from the request headers.
+struct RequestHeaders<'a>(&'a HeaderMap);
+
+impl Extractor for RequestHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
 }
 
 /// HTTP request handler for processing VES events.
@@ -98,63 +286,395 @@ async fn process_event_batch(
 /// curl localhost:8080/eventListener/v7 -H "Content-Type: application/json" -H "X-MinorVersion: 2" -d@ms/res/ves-7.2.1-domain_pnfRegistration.json
 /// ```
 ///
+/// The `traceparent` header of the request is the parent of the `ves.process_event` span, whose
+/// context is passed on in the headers of the produced records.
+///
 pub fn process_event_common(
     req: HttpRequest,
     path: web::Path<String>,
-    data: web::Bytes,
+    payload: Vec<u8>,
     context: web::Data<Arc<Mutex<AppState>>>,
     event_mode: &str,
-) -> impl Responder {
-    let payload = data.into_iter().collect();
+) -> HttpResponse {
     let version = path.into_inner();
+    let span = tracing::info_span!("ves.process_event", ves.version = %version, ves.mode = event_mode);
+    trace::set_remote_parent(&span, &RequestHeaders(req.headers()));
+    let _entered = span.enter();
+
+    let events = parse_events(&payload, event_mode).ok();
+    let full_version = requested_version(&version, req.headers());
+    let state = context.get_ref().lock().unwrap();
+
+    if let Some(response) = rate_limited(&state, &req, events.as_ref().map(Events::events).unwrap_or_default()) {
+        return response;
+    }
 
     let result = validate_request(
-        &version.as_str(),
+        version.as_str(),
         req.headers(),
-        &payload,
-        event_mode,
-        &context.get_ref().lock().unwrap().schemas
+        events.as_ref(),
+        &state.schemas
     );
 
-    if result.is_err() {
-        return HttpResponse::BadRequest()
-            .content_type(ContentType::json())
-            .body(
-                serde_json::to_string(
-                    &json!({ "requestError": result.err().unwrap() })
-                ).unwrap()
-            );
-    }
-
-    for event in parse_events(&payload) {
-        let msg = ProducerRecord {
-            key: "test".into(),
-            topic: context.get_ref().lock().unwrap().topic.to_owned(),
-            headers: HashMap::new(),
-            payload: event,
-        };
+    let schema_version = match result {
+        Ok(schema_version) => schema_version,
+        Err(err) => {
+            queue_dead_letter(&state, &span, &payload, &full_version, &err);
+            return request_error(err);
+        }
+    };
+
+    let schema_id = state.schema_ids.get(&schema_version).copied();
+    for event in events.as_ref().map(Events::events).unwrap_or_default() {
+        queue_event(&state, &span, event, &full_version, schema_id);
+    }
+
+    accepted(&schema_version, &state.schemas).body("")
+}
+
+/// HTTP request handler of `eventBatch` in partial acceptance mode (`http.partial_batch`).
+///
+/// Each event of `eventList` is validated on its own, valid ones are forwarded, invalid ones are
+/// sent to the dead-letter topic if there is one. The response lists the result of each event by
+/// its index in the batch, e.g.,
+///
+/// ```json
+/// {"accepted":1,"rejected":1,"results":[{"index":0,"accepted":true},{"index":1,"accepted":false,"requestError":{"serviceException":{"messageId":"SVC0002","text":"Bad parameter (JSON does not conform to schema)"}}}]}
+/// ```
+///
+/// The request as a whole is rejected only if its version is not supported or it is not a JSON
+/// object with an `eventList`.
+pub fn process_event_batch_partial(
+    req: HttpRequest,
+    path: web::Path<String>,
+    payload: Vec<u8>,
+    context: web::Data<Arc<Mutex<AppState>>>,
+) -> HttpResponse {
+    let version = path.into_inner();
+    let span = tracing::info_span!("ves.process_event", ves.version = %version, ves.mode = "eventList", ves.partial = true);
+    trace::set_remote_parent(&span, &RequestHeaders(req.headers()));
+    let _entered = span.enter();
+
+    let full_version = requested_version(&version, req.headers());
+    let state = context.get_ref().lock().unwrap();
+
+    let events = parse_events(&payload, "eventList").ok().filter(|events| events.document.is_some());
+    if let Some(response) = rate_limited(&state, &req, events.as_ref().map(Events::events).unwrap_or_default()) {
+        return response;
+    }
+
+    let (schema_version, schema) = match resolve_schema(version.as_str(), req.headers(), &state.schemas) {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            queue_dead_letter(&state, &span, &payload, &full_version, &err);
+            return request_error(err);
+        }
+    };
+
+    let Some(mut events) = events else {
+        let err = failed_schema_validation();
+        queue_dead_letter(&state, &span, &payload, &full_version, &err);
+        return request_error(err);
+    };
+
+    let summary = validate_batch(&mut events, schema);
+    let schema_id = state.schema_ids.get(&schema_version).copied();
+    for (event, result) in events.events().iter().zip(&summary.results) {
+        match &result.request_error {
+            None => queue_event(&state, &span, event, &full_version, schema_id),
+            Some(err) => queue_dead_letter(&state, &span, event.raw.as_bytes(), &full_version, err),
+        }
+    }
+
+    if summary.rejected > 0 {
+        log::warn!("{} of {} events of the batch rejected", summary.rejected, summary.results.len());
+    }
+
+    accepted(&schema_version, &state.schemas).json(summary)
+}
+
+/// The user name of the `Authorization: Basic` header, without verifying the password.
+fn basic_auth_user(headers: &HeaderMap) -> Option<String> {
+    let credentials = headers.get(AUTHORIZATION)?.to_str().ok()?.strip_prefix("Basic ")?;
+    let credentials = String::from_utf8(BASE64_STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    credentials.split_once(':').map(|(user, _)| user.to_string())
+}
+
+/// The clients of the events with the number of events of each, see [`crate::rate_limit`].
+fn rate_limit_clients(key: RateLimitKey, req: &HttpRequest, events: &[Event]) -> BTreeMap<String, usize> {
+    let client_ip = || req.peer_addr().map_or_else(|| "unknown".to_string(), |x| x.ip().to_string());
+    let mut clients = BTreeMap::new();
+    match key {
+        RateLimitKey::ClientIp => {
+            clients.insert(client_ip(), events.len());
+        }
+        RateLimitKey::User => {
+            clients.insert(basic_auth_user(req.headers()).unwrap_or_else(client_ip), events.len());
+        }
+        RateLimitKey::ReportingEntity if events.is_empty() => {
+            clients.insert(client_ip(), 0);
+        }
+        RateLimitKey::ReportingEntity => {
+            for event in events {
+                let client = event.header.reporting_entity_name.clone().unwrap_or_else(client_ip);
+                *clients.entry(client).or_default() += 1;
+            }
+        }
+    }
+    clients
+}
+
+/// Takes a token for each event from the bucket of its client. Returns the 429 response with
+/// `Retry-After` in seconds if there are not enough of them.
+fn rate_limited(state: &AppState, req: &HttpRequest, events: &[Event]) -> Option<HttpResponse> {
+    let limiter = state.rate_limiter.as_ref()?;
+    let clients = rate_limit_clients(limiter.key(), req, events);
+    let requests = clients.iter().map(|(client, events)| (client.as_str(), *events)).collect::<Vec<_>>();
+    let wait = limiter.acquire_all(&requests, Instant::now()).err()?;
+
+    log::debug!("Rate limit of {:?} exceeded", clients.keys().collect::<Vec<_>>());
+    let mut response = request_error(too_many_requests());
+    response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(wait.as_secs_f64().ceil() as u64));
+    Some(response)
+}
+
+/// The response of an accepted request.
+///
+/// From VES 7 on, the version headers tell the version the request was validated against and
+/// the latest supported version of the major version.
+fn accepted(version: &SemVer, schemas: &HashMap<SemVer, JSONSchema>) -> HttpResponseBuilder {
+    let mut response = HttpResponse::Accepted();
+    if has_version_headers(version.major) {
+        let latest = schemas.keys().filter(|x| x.major == version.major).max().unwrap_or(version);
+        response
+            .insert_header((HEADER_MINOR_VERSION, version.minor as u32))
+            .insert_header((HEADER_PATCH_VERSION, version.patch as u32))
+            .insert_header((HEADER_LATEST_VERSION, latest.to_string()));
+    }
+    response
+}
+
+/// The requested VES version, e.g., `v7.2.1`, as it is given in the path and the headers.
+///
+/// Before VES 7 only the path is used, e.g., `v5`.
+fn requested_version(version: &str, headers: &HeaderMap) -> String {
+    if parse_major(version).is_some_and(|major| !has_version_headers(major)) {
+        return version.to_string();
+    }
+    format!(
+        "{}.{}.{}",
+        version,
+        headers.get_str_or(HEADER_MINOR_VERSION, "0").unwrap_or("?"),
+        headers.get_str_or(HEADER_PATCH_VERSION, "1").unwrap_or("?"),
+    )
+}
+
+/// Creates the record of an event, with the trace context of `span`.
+fn event_record(topic: &str, span: &tracing::Span, event: &[u8], version: &str) -> ProducerRecord {
+    let mut headers = HashMap::new();
+    trace::inject(span, &mut headers);
+    set_origin(&mut headers, version);
+
+    ProducerRecord {
+        key: "test".into(),
+        topic: topic.to_owned(),
+        headers,
+        payload: event.to_vec(),
+    }
+}
+
+/// Queues the event to be produced to the output topic, in the schema registry wire format if
+/// the ID of its schema is given.
+///
+/// Duplicates are dropped or sent to the dead-letter topic, see [`crate::dedup`].
+fn queue_event(state: &AppState, span: &tracing::Span, event: &Event, version: &str, schema_id: Option<u32>) {
+    let header = &event.header;
+    let event = event.raw.as_bytes();
+    if let Some(dedup) = state.dedup.as_ref().filter(|x| x.is_duplicate(header, Instant::now())) {
+        log::debug!("Duplicate event dropped");
+        if let Some(topic) = state.dead_letter_topic.as_ref().filter(|_| dedup.dead_letter()) {
+            let record = event_record(topic, span, event, version);
+            queue(state, to_dead_letter(record, topic, FailureReason::Duplicate, "Duplicate event"));
+        }
+        return;
+    }
+
+    let mut record = event_record(&state.topic, span, event, version);
+    if let Some(schema_id) = schema_id {
+        let payload = Registered { schema_id, value: event };
+        record.payload = payload.serialize().expect("Bytes are always serializable").into_owned();
+    }
+    queue(state, record);
+
+    if let Some(clear) = state.heartbeats.as_ref().and_then(|x| x.observe(header, Utc::now())) {
+        queue(state, fault_record(&state.topic, &clear, &state.schema_ids));
+    }
+}
+
+/// Queues the rejected event to the dead-letter topic, if there is one.
+fn queue_dead_letter(state: &AppState, span: &tracing::Span, event: &[u8], version: &str, err: &VesError) {
+    let Some(topic) = &state.dead_letter_topic else {
+        return;
+    };
+
+    let record = to_dead_letter(
+        event_record(topic, span, event, version),
+        topic,
+        FailureReason::Validation,
+        &err.to_string(),
+    );
+    queue(state, record);
+}
+
+fn queue(state: &AppState, record: ProducerRecord) {
+    let _ = state.tx.send(record)
+        .map_err(|err| {
+            log::warn!("Failed to queue message: {}", err);
+            err
+        });
+}
+
+/// Result of a single event of a batch, see [`process_event_batch_partial`].
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventResult {
+    /// Position of the event in `eventList`
+    pub index: usize,
+    pub accepted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_error: Option<VesError>,
+}
+
+/// Per-event results of a batch.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BatchSummary {
+    pub accepted: usize,
+    pub rejected: usize,
+    pub results: Vec<EventResult>,
+}
+
+/// Validates each event of a batch on its own, all of them are accepted if `schema` is `None`.
+pub fn validate_batch(events: &mut Events, schema: Option<&JSONSchema>) -> BatchSummary {
+    let valid = match schema {
+        Some(schema) => events.validate_each(schema),
+        None => vec![true; events.events.len()],
+    };
+    let results = valid
+        .into_iter()
+        .enumerate()
+        .map(|(index, valid)| EventResult {
+            index,
+            accepted: valid,
+            request_error: (!valid).then(failed_schema_validation),
+        })
+        .collect::<Vec<_>>();
+
+    let accepted = results.iter().filter(|x| x.accepted).count();
+    BatchSummary {
+        accepted,
+        rejected: results.len() - accepted,
+        results,
+    }
+}
+
+/// Reader of a JSON document which parses each value into a [`Value`] and keeps the slice of
+/// the buffer it was parsed from.
+struct Scanner<'a> {
+    data: &'a str,
+    position: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn skip_whitespace(&mut self) {
+        let bytes = self.data.as_bytes();
+        while matches!(bytes.get(self.position), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    /// Consumes `byte` if it is next after the whitespace.
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.data.as_bytes().get(self.position) == Some(&byte);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
 
-        let _ = context
-            .get_ref()
-            .lock()
-            .unwrap()
-            .tx.send(msg)
-                .map_err(|err| {
-                    log::warn!("Failed to queue message: {}", err);
-                    err
-                });
+    fn expect(&mut self, byte: u8) -> Result<(), serde_json::Error> {
+        match self.eat(byte) {
+            true => Ok(()),
+            false => Err(json_error(format!("expected `{}` at byte {}", byte as char, self.position))),
+        }
     }
 
-    HttpResponse::Accepted()
-        .insert_header((HEADER_MINOR_VERSION, 1))           // TODO(feat): implement the correct response
-        .insert_header((HEADER_PATCH_VERSION, 1))
-        .insert_header((HEADER_LATEST_VERSION, "7.1.1"))
-        .body("")
+    /// Parses the next value and returns it with its slice.
+    fn value<T: DeserializeOwned>(&mut self) -> Result<(T, &'a str), serde_json::Error> {
+        self.skip_whitespace();
+        let start = self.position;
+        let mut values = serde_json::Deserializer::from_str(&self.data[start..]).into_iter::<T>();
+        let value = values.next().unwrap_or_else(|| Err(json_error("unexpected end of input")))?;
+        self.position += values.byte_offset();
+        Ok((value, &self.data[start..self.position]))
+    }
+}
+
+fn json_error(message: impl std::fmt::Display) -> serde_json::Error {
+    <serde_json::Error as serde::de::Error>::custom(message)
+}
+
+/// An event of a request body.
+#[derive(Debug)]
+pub struct Event<'a> {
+    /// The raw JSON slice of the original buffer
+    pub raw: &'a str,
+    pub header: EventHeader,
+}
+
+/// The events of a request body under a root key, `event` or `eventList`.
+///
+/// The body is parsed once into the document of the schema validation. Each event is parsed
+/// on its own in the same pass, which keeps its raw JSON slice: it is produced as it is, neither
+/// copied nor re-serialized. Its [`EventHeader`] is read from the parsed event.
+#[derive(Debug, Default)]
+pub struct Events<'a> {
+    events: Vec<Event<'a>>,
+    /// The whole body, `None` if the root key is missing
+    document: Option<Value>,
+}
+
+impl<'a> Events<'a> {
+    /// The events under the root key, empty if it is missing.
+    pub fn events(&self) -> &[Event<'a>] {
+        &self.events
+    }
+
+    /// Validates each event of `eventList` on its own as if it was sent alone in the `event` key,
+    /// moving it in and out of the document instead of copying it.
+    fn validate_each(&mut self, schema: &JSONSchema) -> Vec<bool> {
+        let Some(Value::Array(events)) = self.document.as_mut().and_then(|x| x.get_mut("eventList")) else {
+            return vec![false; self.events.len()];
+        };
+
+        events
+            .iter_mut()
+            .map(|event| {
+                let mut single = json!({ "event": event.take() });
+                let valid = schema.validate(&single).is_ok();
+                *event = single["event"].take();
+                valid
+            })
+            .collect()
+    }
 }
 
 /// Parse events out of the JSON.
 ///
-/// Either a single event in `event` key or multiple events in `eventList` key.
+/// Either a single event in `event` key or multiple events in `eventList` key, as given by
+/// `root_key`. The payload is parsed only once, the result is used for the validation, for
+/// splitting it into events and for their header fields, see [`Events`]. The body must be an
+/// object, and `eventList` an array.
 ///
 /// ## Examples
 ///
@@ -172,10 +692,11 @@ pub fn process_event_common(
 /// let expected = vec![
 ///     r#"{"a":1}"#
 /// ];
-/// # let input = event.to_string().as_bytes().to_vec();
-/// # let result: Vec<_> = expected.into_iter().map(|x| x.as_bytes().to_vec()).collect();
+/// # let input = event.to_string();
+/// # let events = parse_events(input.as_bytes(), "event").unwrap();
+/// # let result: Vec<_> = events.events().iter().map(|x| x.raw).collect();
 ///
-/// # assert_eq!(parse_events(&input), result);
+/// # assert_eq!(result, expected);
 /// ```
 ///
 /// ### Event batch
@@ -194,47 +715,85 @@ pub fn process_event_common(
 ///     r#"{"a":1}"#,
 ///     r#"{"b":2}"#
 /// ];
-/// # let input = event_list.to_string().as_bytes().to_vec();
-/// # let result: Vec<_> = expected.into_iter().map(|x| x.as_bytes().to_vec()).collect();
+/// # let input = event_list.to_string();
+/// # let events = parse_events(input.as_bytes(), "eventList").unwrap();
+/// # let result: Vec<_> = events.events().iter().map(|x| x.raw).collect();
 ///
-/// # assert_eq!(parse_events(&input), result);
+/// # assert_eq!(result, expected);
 /// ```
 ///
-pub fn parse_events(data: &Vec<u8>) -> Vec<Vec<u8>> {        // TODO(perf): slice?
-    str::from_utf8(&data).map(
-        |string_data| serde_json::from_str::<serde_json::Value>(string_data).map(
-            |json_data| match json_data["event"] {
-                serde_json::Value::Null => {
-                    json_data["eventList"]
-                        .as_array()
-                        .expect("")
-                        .iter()
-                        .map(|event| { event.to_string().as_bytes().to_vec() })
-                        .collect()
+/// Invalid UTF-8 or JSON is an error.
+///
+/// ```
+/// # use ves::http_server::parse_events;
+/// assert!(parse_events(&[0x01, 0xFF], "event").is_err());
+/// ```
+///
+pub fn parse_events<'a>(data: &'a [u8], root_key: &str) -> Result<Events<'a>, serde_json::Error> {
+    let mut scanner = Scanner { data: str::from_utf8(data).map_err(json_error)?, position: 0 };
+    let mut document = serde_json::Map::new();
+    let mut events = None;
+
+    scanner.expect(b'{')?;
+    if !scanner.eat(b'}') {
+        loop {
+            let (key, _) = scanner.value::<String>()?;
+            scanner.expect(b':')?;
+
+            let value = match key.as_str() {
+                "event" if key == root_key => {
+                    let (event, raw) = scanner.value::<Value>()?;
+                    events = Some(vec![Event { raw, header: EventHeader::new(&event) }]);
+                    event
+                }
+                "eventList" if key == root_key => {
+                    let mut list = Vec::new();
+                    let mut values = Vec::new();
+                    scanner.expect(b'[')?;
+                    if !scanner.eat(b']') {
+                        loop {
+                            let (event, raw) = scanner.value::<Value>()?;
+                            list.push(Event { raw, header: EventHeader::new(&event) });
+                            values.push(event);
+                            if scanner.eat(b']') {
+                                break;
+                            }
+                            scanner.expect(b',')?;
+                        }
+                    }
+                    events = Some(list);
+                    Value::Array(values)
                 }
-                _ => vec![json_data["event"].to_string().as_bytes().to_vec()]
+                _ => scanner.value::<Value>()?.0,
+            };
+            document.insert(key, value);
+
+            if scanner.eat(b'}') {
+                break;
             }
-        )
-    ).expect("Logic error in validation").expect("Logic error again")
+            scanner.expect(b',')?;
+        }
+    }
+
+    scanner.skip_whitespace();
+    if scanner.position < scanner.data.len() {
+        return Err(json_error(format!("trailing characters at byte {}", scanner.position)));
+    }
+
+    Ok(match events {
+        Some(events) => Events { events, document: Some(Value::Object(document)) },
+        None => Events::default(),
+    })
 }
 
 /// Validate event based on JSON schema with some pre-checks.
 ///
-/// The `data` is okay if it is:
-/// - a valid string, so no binary data
-/// - and a valid JSON,
-/// - and it has "event" or "eventList" for root key
+/// The `events` are okay if
+/// - they have the requested root key, "event" or "eventList"
 /// - and also valid according to the schema
 ///
-pub fn validate_event(data: &Vec<u8>, schema: &JSONSchema, root_key: &str) -> bool {
-    str::from_utf8(&data).is_ok_and(
-        |string_data| serde_json::from_str::<serde_json::Value>(string_data).is_ok_and(
-            |json_data| match json_data[root_key] {
-                serde_json::Value::Null => false,
-                _ => schema.validate(&json_data).is_ok()
-            }
-        )
-    )
+pub fn validate_event(events: &Events, schema: &JSONSchema) -> bool {
+    events.document.as_ref().is_some_and(|document| schema.validate(document).is_ok())
 }
 
 trait DefaultStringGetter {
@@ -255,29 +814,69 @@ impl DefaultStringGetter for HeaderMap {
 ///
 /// The request is valid if all of the followings are true (checked in order):
 /// - Content-Type is `application/json` (TODO)
-/// - Message size is below 2 MB (TODO)
-/// - `version` path is for example `v7`, where the number matches one of the supported schemas
-/// - The given minor and patch version in the HTTP headers are supported;
-///   default is 0 and 1 for minor and patch respectively (full example: v7.0.1)
-/// - Finally, the event is valid according to [`validate_event`]
+/// - Message size is below the limit, checked while reading the body, see [`decode_body`]
+/// - The version is supported, see [`resolve_schema`]
+/// - Finally, the payload is a valid JSON ([`parse_events`]) and the event is valid according to
+///   [`validate_event`]
 ///
+/// Returns the version of the schema the request conforms to.
 fn validate_request(
     version: &str,
     headers: &HeaderMap,
-    data: &Vec<u8>,
-    event_mode: &str,
+    events: Option<&Events>,
     schemas: &HashMap<SemVer, JSONSchema>
 )
-    -> Result<(), VesError>
+    -> Result<SemVer, VesError>
 {
-    if !version.starts_with("v") {
-        return Err(invalid_api_version());
+    let (schema_version, schema) = resolve_schema(version, headers, schemas)?;
+    if let Some(schema) = schema {
+        if !events.is_some_and(|events| validate_event(events, schema)) {
+            return Err(failed_schema_validation());
+        }
     }
 
-    let Ok(major) = version[1..].parse() else {
+    Ok(schema_version)
+}
+
+/// The major version in the path, e.g., 7 of `v7`.
+fn parse_major(version: &str) -> Option<u16> {
+    version.strip_prefix('v')?.parse().ok()
+}
+
+/// Whether the minor and patch versions are given in the `X-MinorVersion` and `X-PatchVersion`
+/// headers, which VES 7 introduced.
+fn has_version_headers(major: u16) -> bool {
+    major >= VERSION_HEADERS_SINCE
+}
+
+/// Finds the schema of the requested VES version.
+///
+/// - `version` path is for example `v7`, where the number matches one of the supported schemas
+/// - The given minor and patch version in the HTTP headers are supported;
+///   default is 0 and 1 for minor and patch respectively (full example: v7.0.1)
+/// - Before VES 7, e.g., `v5`, the headers are ignored and the latest schema of the major
+///   version is used
+///
+/// The latest patch of the minor version is used, unless a patch above 1 is requested, in which
+/// case the events are not validated and `None` is returned with the requested version.
+fn resolve_schema<'a>(
+    version: &str,
+    headers: &HeaderMap,
+    schemas: &'a HashMap<SemVer, JSONSchema>
+)
+    -> Result<(SemVer, Option<&'a JSONSchema>), VesError>
+{
+    let Some(major) = parse_major(version) else {
         return Err(invalid_api_version());
     };
 
+    if !has_version_headers(major) {
+        let Some((latest, schema)) = schemas.iter().filter(|(x, _)| x.major == major).max_by_key(|(x, _)| *x) else {
+            return Err(invalid_api_version());
+        };
+        return Ok((latest.clone(), Some(schema)));
+    }
+
     let Ok(Ok(minor)) = headers.get_str_or(HEADER_MINOR_VERSION, "0").map(|x| x.parse::<u16>()) else {
         return Err(invalid_api_version());
     };
@@ -290,37 +889,38 @@ fn validate_request(
         if schemas.keys().filter(|x| **x == SemVer{ major, minor, patch }).count() == 0 {
             return Err(invalid_api_version());
         }
-    } else {
-        let patch_versions = schemas
-            .keys()
-            .filter(|supported_version| supported_version.major == major && supported_version.minor == minor)
-            .map(|supported_version| supported_version.patch)
-            .collect::<Vec<u16>>();
-
-        if patch_versions.len() == 0 {
-            return Err(invalid_api_version());
-        }
+        return Ok((SemVer{ major, minor, patch }, None));
+    }
 
-        let latest_patch = patch_versions.into_iter().max().expect("Logic error!");
-        let Some(schema) = schemas.get(&SemVer{ major, minor, patch: latest_patch }) else {
-            return Err(invalid_api_version());
-        };
+    let patch_versions = schemas
+        .keys()
+        .filter(|supported_version| supported_version.major == major && supported_version.minor == minor)
+        .map(|supported_version| supported_version.patch)
+        .collect::<Vec<u16>>();
 
-        if !validate_event(&data, schema, event_mode) {
-            return Err(failed_schema_validation());
-        }
+    if patch_versions.is_empty() {
+        return Err(invalid_api_version());
     }
 
-    Ok(())
+    let latest_patch = patch_versions.into_iter().max().expect("Logic error!");
+    let schema_version = SemVer{ major, minor, patch: latest_patch };
+    let Some(schema) = schemas.get(&schema_version) else {
+        return Err(invalid_api_version());
+    };
+
+    Ok((schema_version, Some(schema)))
 }
 
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
-    use actix_web::http::header::{HeaderName, HeaderValue};
+    use actix_web::{http::header::HeaderName, test as actix_test};
 
     use super::*;
 
+    const HEADER_CONTENT_TYPE: &str = "Content-Type";
+    const CONTENT_TYPE_JSON: &str = "application/json";
+
     fn schema() -> JSONSchema {
         let x = serde_json::from_str(include_str!("../res/schemas/CommonEventFormat_30.2.1.json")).expect("Invalid JSON");
         JSONSchema::compile(&x).expect("Invalid JSON schema")
@@ -332,34 +932,134 @@ mod tests {
         schemas
     }
 
+    /// A state with the 7.2.1 schema and every optional feature disabled.
+    fn app_state(tx: std::sync::mpsc::Sender<ers::kafka::ProducerRecord>) -> AppState {
+        AppState {
+            topic: "output_1".into(),
+            tx,
+            schemas: schema_map(),
+            schema_sources: BTreeMap::new(),
+            schema_ids: HashMap::new(),
+            max_message_size: 1024,
+            partial_batch: false,
+            dead_letter_topic: None,
+            spool_metrics: None,
+            heartbeats: None,
+            dedup: None,
+            rate_limiter: None,
+        }
+    }
+
+    fn validate(input: &[u8], root_key: &str) -> bool {
+        parse_events(input, root_key).is_ok_and(|events| validate_event(&events, &schema()))
+    }
+
     #[test]
     fn test_validate_invalid_utf8() {
         let input = vec![0x01, 0xFF];
-        assert!(!validate_event(&input, &schema(), "event"));
+        assert!(!validate(&input, "event"));
     }
 
     #[test]
     fn test_validate_invalid_json() {
-        let input = "{".into();
-        assert!(!validate_event(&input, &schema(), "event"));
+        assert!(!validate(b"{", "event"));
     }
 
     #[test]
     fn test_validate_invalid_event_ves_schema() {
-        let input = r#"{"event": 1}"#.into();
-        assert!(!validate_event(&input, &schema(), "event"));
+        assert!(!validate(br#"{"event": 1}"#, "event"));
     }
 
     #[test]
     fn test_validate_invalid_event_root_key_check() {
-        let input = r#"{"bla": 1}"#.into();
-        assert!(!validate_event(&input, &schema(), "event"));
+        assert!(!validate(br#"{"bla": 1}"#, "event"));
     }
 
     #[test]
     fn test_validate_valid_event() {
-        let input = include_str!("../res/ves-7.2.1-domain_pnfRegistration.json").into();
-        assert!(validate_event(&input, &schema(), "event"));
+        let input = include_bytes!("../res/ves-7.2.1-domain_pnfRegistration.json");
+        assert!(validate(input, "event"));
+        assert!(!validate(input, "eventList"));
+    }
+
+    #[test]
+    fn test_validate_valid_event_batch() {
+        let input = include_bytes!("../res/ves-7.2.1-batch.json");
+        assert!(validate(input, "eventList"));
+
+        let events = parse_events(input, "eventList").unwrap();
+        assert_eq!(events.events().len(), 2);
+        assert!(events.events().iter().all(|event| std::str::from_utf8(input).unwrap().contains(event.raw)));
+        assert_eq!(events.events()[1].header.domain.as_deref(), Some("fault"));
+    }
+
+    #[test]
+    fn test_parse_events_whole_document() {
+        let event = std::str::from_utf8(include_bytes!("../res/ves-7.2.1-domain_pnfRegistration.json")).unwrap();
+        let event = serde_json::from_str::<Value>(event).unwrap()["event"].to_string();
+
+        // The rest of the body is validated too
+        let input = format!(r#"{{ "event" : {} , "eventList": 5 }}"#, event);
+        let events = parse_events(input.as_bytes(), "event").unwrap();
+        assert_eq!(events.events()[0].raw, event);
+        assert!(!validate_event(&events, &schema()));
+
+        let input = br#" { "eventList" : [ {"a": 1} ,{"b": [2]}] } "#;
+        let events = parse_events(input, "eventList").unwrap();
+        assert_eq!(events.events().iter().map(|x| x.raw).collect::<Vec<_>>(), [r#"{"a": 1}"#, r#"{"b": [2]}"#]);
+        assert!(parse_events(br#"{"eventList": []}"#, "eventList").unwrap().events().is_empty());
+
+        assert!(parse_events(br#"{"event": {}} {}"#, "event").is_err());
+        assert!(parse_events(br#"{"event": {}, "eventList": [1,]}"#, "event").is_err());
+        assert!(parse_events(br#"{"eventList": 5}"#, "eventList").is_err());
+        assert!(parse_events(br#"[{"event": {}}]"#, "event").is_err());
+        assert!(parse_events(br#"{"event": {}"#, "event").is_err());
+    }
+
+    #[test]
+    fn test_validate_batch_partially() {
+        let input = std::str::from_utf8(include_bytes!("../res/ves-7.2.1-batch.json")).unwrap();
+        let mut batch: Value = serde_json::from_str(input).unwrap();
+        batch["eventList"][1]["commonEventHeader"]["priority"] = json!("Urgent");
+        let input = batch.to_string();
+
+        let mut events = parse_events(input.as_bytes(), "eventList").unwrap();
+        let summary = validate_batch(&mut events, Some(&schema()));
+
+        assert_eq!((summary.accepted, summary.rejected), (1, 1));
+        assert_eq!(summary.results[0], EventResult { index: 0, accepted: true, request_error: None });
+        assert_eq!(summary.results[1].request_error, Some(failed_schema_validation()));
+        assert_eq!(validate_batch(&mut events, None).rejected, 0);
+        // The events are back in the document after the validation
+        assert!(!validate_event(&events, &schema()));
+        assert_eq!(validate_batch(&mut events, Some(&schema())), summary);
+    }
+
+    #[test]
+    fn test_batch_summary_format() {
+        let summary = BatchSummary {
+            accepted: 0,
+            rejected: 1,
+            results: vec![EventResult { index: 0, accepted: false, request_error: Some(failed_schema_validation()) }],
+        };
+
+        assert_eq!(
+            serde_json::to_value(summary).unwrap(),
+            json!({
+                "accepted": 0,
+                "rejected": 1,
+                "results": [{
+                    "index": 0,
+                    "accepted": false,
+                    "requestError": {
+                        "serviceException": {
+                            "messageId": "SVC0002",
+                            "text": "Bad parameter (JSON does not conform to schema)"
+                        }
+                    }
+                }]
+            })
+        );
     }
 
     #[test]
@@ -368,15 +1068,144 @@ mod tests {
         headers.insert(HeaderName::from_str(HEADER_CONTENT_TYPE).unwrap(), HeaderValue::from_str(CONTENT_TYPE_JSON).unwrap());
         headers.insert(HeaderName::from_str(HEADER_MINOR_VERSION).unwrap(), HeaderValue::from_str("2").unwrap());
 
-        let data = include_str!("../res/ves-7.2.1-domain_pnfRegistration.json").to_string().into_bytes();
+        let data = include_bytes!("../res/ves-7.2.1-domain_pnfRegistration.json");
+        let events = parse_events(data, "event").ok();
+
+        assert_eq!(
+            validate_request("v7", &headers, events.as_ref(), &schema_map()),
+            Ok(SemVer::from_str("7.2.1").unwrap())
+        );
+        assert_eq!(validate_request("v7", &headers, None, &schema_map()), Err(failed_schema_validation()));
+    }
+
+    #[test]
+    fn test_queue_event_registered() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let state = AppState {
+            schema_ids: HashMap::from([(SemVer::from_str("7.2.1").unwrap(), 3)]),
+            ..app_state(tx)
+        };
+
+        let events = parse_events(br#"{"event": {}}"#, "event").unwrap();
+        let span = tracing::Span::none();
+        queue_event(&state, &span, &events.events()[0], "v7.2.1", None);
+        queue_event(&state, &span, &events.events()[0], "v7.2.1", Some(3));
+        assert_eq!(rx.recv().unwrap().payload, b"{}");
+        assert_eq!(rx.recv().unwrap().payload, [0, 0, 0, 0, 3, b'{', b'}']);
+    }
+
+    #[test]
+    fn test_queue_event_duplicate() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let settings = crate::settings::DedupSettings { enable: true, dead_letter: true, ..Default::default() };
+        let state = AppState {
+            dead_letter_topic: Some("output_1_dlt".into()),
+            dedup: Some(crate::dedup::Deduplicator::new(&settings)),
+            ..app_state(tx)
+        };
+
+        let events = parse_events(br#"{"event": {"commonEventHeader": {"sourceName": "a", "eventId": "1", "sequence": 1}}}"#, "event").unwrap();
+        let span = tracing::Span::none();
+        queue_event(&state, &span, &events.events()[0], "v7.2.1", None);
+        queue_event(&state, &span, &events.events()[0], "v7.2.1", None);
+        assert_eq!(rx.recv().unwrap().topic, "output_1");
+        let duplicate = rx.recv().unwrap();
+        assert_eq!(duplicate.topic, "output_1_dlt");
+        assert_eq!(duplicate.headers[crate::dead_letter::HEADER_FAILURE_REASON], b"duplicate");
+    }
+
+    #[actix_web::test]
+    async fn test_schema_endpoints() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let definition = include_str!("../res/schemas/CommonEventFormat_30.2.1.json");
+        let source = SchemaSource { path: "CommonEventFormat_30.2.1.json".into(), sha256: "ab".into(), definition: definition.into() };
+        let state = Arc::new(Mutex::new(AppState {
+            schema_sources: BTreeMap::from([(SemVer::from_str("7.2.1").unwrap(), source)]),
+            ..app_state(tx)
+        }));
+        let app = actix_test::init_service(
+            App::new().app_data(web::Data::new(state)).service(list_schemas).service(get_schema)
+        ).await;
+
+        let list: Value = actix_test::call_and_read_body_json(&app, actix_test::TestRequest::get().uri("/schemas").to_request()).await;
+        assert_eq!(list, json!({ "schemas": [{ "version": "7.2.1", "path": "CommonEventFormat_30.2.1.json", "sha256": "ab" }] }));
+
+        let body = actix_test::call_and_read_body(&app, actix_test::TestRequest::get().uri("/schemas/v7.2.1").to_request()).await;
+        assert_eq!(body, definition.as_bytes());
+
+        let response = actix_test::call_service(&app, actix_test::TestRequest::get().uri("/schemas/7.1.0").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_rate_limited() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let settings = crate::settings::RateLimitSettings { enable: true, key: RateLimitKey::User, rate: 1.0, burst: 2, max_clients: 10 };
+        let state = AppState {
+            rate_limiter: Some(crate::rate_limit::RateLimiter::new(&settings)),
+            ..app_state(tx)
+        };
+        // alice:secret and bob:secret
+        let alice = actix_test::TestRequest::default().insert_header((AUTHORIZATION, "Basic YWxpY2U6c2VjcmV0")).to_http_request();
+        let bob = actix_test::TestRequest::default().insert_header((AUTHORIZATION, "Basic Ym9iOnNlY3JldA==")).to_http_request();
+        let events = parse_events(include_bytes!("../res/ves-7.2.1-domain_pnfRegistration.json"), "event").unwrap();
+        let events = events.events();
+
+        assert!(rate_limited(&state, &alice, events).is_none());
+        assert!(rate_limited(&state, &alice, events).is_none());
+        let response = rate_limited(&state, &alice, events).unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "1");
+        assert!(rate_limited(&state, &bob, events).is_none());
+    }
+
+    #[test]
+    fn test_rate_limit_clients() {
+        let req = actix_test::TestRequest::default().peer_addr("10.0.0.1:4000".parse().unwrap()).to_http_request();
+        let batch = br#"{"eventList": [
+            {"commonEventHeader": {"reportingEntityName": "a"}},
+            {"commonEventHeader": {"reportingEntityName": "b"}},
+            {"commonEventHeader": {"reportingEntityName": "a"}},
+            {"commonEventHeader": {}}
+        ]}"#;
+        let events = parse_events(batch, "eventList").unwrap();
+
+        assert_eq!(
+            rate_limit_clients(RateLimitKey::ReportingEntity, &req, events.events()),
+            BTreeMap::from([("a".into(), 2), ("b".into(), 1), ("10.0.0.1".into(), 1)])
+        );
+        assert_eq!(rate_limit_clients(RateLimitKey::ClientIp, &req, events.events()), BTreeMap::from([("10.0.0.1".into(), 4)]));
+        assert_eq!(rate_limit_clients(RateLimitKey::ReportingEntity, &req, &[]), BTreeMap::from([("10.0.0.1".into(), 0)]));
+    }
+
+    #[test]
+    fn test_legacy_request() {
+        let legacy = serde_json::from_str(include_str!("../res/schemas/CommonEventFormat_28.4.1.json")).unwrap();
+        let mut schemas = schema_map();
+        schemas.insert(SemVer::from_str("5.4.1").unwrap(), JSONSchema::compile(&legacy).unwrap());
+
+        // The version headers of VES 7 are ignored
+        let mut headers = HeaderMap::new();
+        headers.insert(HeaderName::from_str(HEADER_MINOR_VERSION).unwrap(), HeaderValue::from_str("2").unwrap());
+        assert_eq!(requested_version("v5", &headers), "v5");
+
+        let fault = parse_events(include_bytes!("../res/ves-5.4.1-domain_fault.json"), "event").ok();
+        let pnf = parse_events(include_bytes!("../res/ves-7.2.1-domain_pnfRegistration.json"), "event").ok();
+        assert_eq!(validate_request("v5", &headers, fault.as_ref(), &schemas), Ok(SemVer::from_str("5.4.1").unwrap()));
+        assert_eq!(validate_request("v5", &headers, pnf.as_ref(), &schemas), Err(failed_schema_validation()));
+        assert_eq!(validate_request("v7", &headers, fault.as_ref(), &schemas), Err(failed_schema_validation()));
+        assert_eq!(validate_request("v7", &headers, pnf.as_ref(), &schemas), Ok(SemVer::from_str("7.2.1").unwrap()));
+        assert!(validate_request("v6", &headers, fault.as_ref(), &schemas).is_err());
 
-        assert!(validate_request("v7", &headers, &data, "event", &schema_map()).is_ok());
+        let response = accepted(&SemVer::from_str("5.4.1").unwrap(), &schemas).finish();
+        assert!(response.headers().get(HEADER_LATEST_VERSION).is_none());
+        let response = accepted(&SemVer::from_str("7.2.1").unwrap(), &schemas).finish();
+        assert_eq!(response.headers().get(HEADER_LATEST_VERSION).unwrap(), "7.2.1");
     }
 
     #[test]
     fn test_invalid_request_version() {
         let headers = HeaderMap::new();
-        let data = Vec::new();
-        assert!(validate_request("7", &headers, &data, "event", &schema_map()).is_err());
+        assert!(validate_request("7", &headers, None, &schema_map()).is_err());
     }
 }
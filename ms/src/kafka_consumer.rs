@@ -6,7 +6,8 @@ use rdkafka::error::KafkaResult;
 use rdkafka::message::{Headers, Message};
 use rdkafka::topic_partition_list::TopicPartitionList;
 
-use crate::MyApp;
+use ers::{kafka::ConsumerHeaders, trace};
+use ves::settings::Settings;
 
 struct CustomContext;
 
@@ -29,16 +30,16 @@ impl ConsumerContext for CustomContext {
 // A type alias with your custom consumer can be created for convenience.
 type LoggingConsumer = StreamConsumer<CustomContext>;
 
-pub async fn consume_topic_1_and_print(my_app: MyApp) {
-    let enable = my_app.get_param_bool("interfaces.southbound.kafka.input_1.enable");
-    if enable == false {
+pub async fn consume_topic_1_and_print(settings: Settings) {
+    let input = settings.interfaces.southbound.kafka.input_1;
+    if !input.enable {
         return;
     }
 
-    let brokers = my_app.get_param("kafka.address");
-    let topic = my_app.get_param("interfaces.southbound.kafka.input_1.topic");
-    let group_id = my_app.get_param("interfaces.southbound.kafka.input_1.group_id");
-    let enable_auto_commit = my_app.get_param("interfaces.southbound.kafka.input_1.enable_auto_commit");
+    let brokers = settings.kafka.address;
+    let topic = input.topic;
+    let group_id = input.group_id;
+    let enable_auto_commit = input.enable_auto_commit.to_string();
 
     let context = CustomContext;
 
@@ -64,6 +65,18 @@ pub async fn consume_topic_1_and_print(my_app: MyApp) {
         match consumer.recv().await {
             Err(e) => log::error!("Kafka error: {}", e),
             Ok(m) => {
+                let span = tracing::info_span!(
+                    "kafka.receive",
+                    messaging.system = "kafka",
+                    messaging.source.name = m.topic(),
+                    messaging.kafka.partition = m.partition(),
+                    messaging.kafka.offset = m.offset(),
+                );
+                if let Some(headers) = m.headers() {
+                    trace::set_remote_parent(&span, &ConsumerHeaders(headers));
+                }
+                let _entered = span.enter();
+
                 let payload = match m.payload_view::<str>() {
                     None => "",
                     Some(Ok(s)) => s,
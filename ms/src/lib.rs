@@ -1,3 +1,15 @@
+pub mod compression;
+pub mod dead_letter;
+pub mod dedup;
+pub mod event;
+pub mod forwarder;
+pub mod generator;
+pub mod heartbeat;
 pub mod http_server;
+pub mod metrics;
+pub mod rate_limit;
+pub mod schemas;
+pub mod settings;
+pub mod spool;
 pub mod types;
 pub mod ves;
@@ -1,155 +1,189 @@
 use std::{
-    env,
     thread,
     thread::JoinHandle,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    process,
     sync::{
         Arc,
         Mutex,
         mpsc,
         mpsc::Receiver,
     },
+    time::Duration,
 };
 
+use chrono::Utc;
+
 use ers::{
     SemVer,
+    config,
+    logging,
+    trace,
     kafka::{
+        CachedSchemaRegistry,
+        HttpSchemaRegistry,
         ProducerRecord,
         KafkaProducer,
     }
 };
 
-use config::Config;
-use jsonschema::JSONSchema;
+mod kafka_consumer;
 
-mod kafka_consumer;     // TODO: this depends on `MyApp`
+const SERVICE_NAME: &str = "ves";
 
 use ves::{
+    dedup::Deduplicator,
+    forwarder::{Fallback, Forwarder},
+    heartbeat::{fault_record, HeartbeatMonitor},
     http_server,
+    rate_limit::RateLimiter,
+    schemas::{self, load_schemas, SchemaFile},
+    settings::Settings,
+    spool::Spool,
     types::AppState
 };
 
-#[derive(Clone)]
-struct MyApp {
-    config: Config,
-}
-
-impl MyApp {
-    fn new_with_prefix(prefix: &str) -> Self {
-        let mut file_path = String::from("./settings");
-        if let Ok(v) = env::var(prefix.to_owned() + "_CONFIG_FILE") {
-            file_path = v.to_owned();
-        }
-        let config = Config::builder()
-            .add_source(config::File::with_name(&file_path))
-            // Add in settings from the environment (with a prefix of APP)
-            // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
-            .add_source(config::Environment::with_prefix(prefix))
-            .build()
-            .expect("Error");
-
-        MyApp { config }
-    }
-
-    fn get_param(&self, key: &str) -> String {
-        self.config
-            .get_string(key)
-            .unwrap_or_else(|_| panic!("{} is missing from configuration", key))
-    }
-
-    fn get_param_bool(&self, key: &str) -> bool {
-        self.config
-            .get_bool(key)
-            .unwrap_or_else(|_| panic!("{} is missing from configuration", key))
-    }
-}
-
-fn read_schema(path: &String) -> JSONSchema {
-    // std::fs::read_to_string(path).map_or("Invalid path",
-        // |content| serde_json::from_str::<serde_json::Value>(content.as_str()).map_or("Invalid JSON",
-            // |json| JSONSchema::compile(&json).or("Invalid JSON 2")
-        // )
-    // )
-
-    JSONSchema::compile(
-        &serde_json::from_str(
-            std::fs::read_to_string(path)
-                .expect(format!("Failed to read file: {}", path).as_str())
-                .as_str()
-        ).expect("Invalid Json schema")
-    ).expect("Invalid Json schema")
-}
-
-fn read_schemas(config: &Config) -> HashMap<SemVer, JSONSchema> {
-    let mut schemas = HashMap::new();
-
-    for (version, filepath) in config.get_table("schemas.apiVersion").unwrap() {
-        schemas.insert(
-            version.parse().expect("Invalid SemVer, most likely an incorrect configuration file!"),
-            read_schema(&filepath.into_string().unwrap())
-        );
+/// Registers the schemas of the output topic, if the registry is enabled.
+fn register_schemas(settings: &Settings, schemas: &BTreeMap<SemVer, SchemaFile>) -> HashMap<SemVer, u32> {
+    if !settings.schema_registry.enable {
+        return HashMap::new();
     }
 
-    schemas
+    let timeout = Duration::from_millis(settings.schema_registry.timeout_ms);
+    let registry = HttpSchemaRegistry::new(&settings.schema_registry.url, timeout)
+        .map(CachedSchemaRegistry::new)
+        .unwrap_or_else(|err| {
+            log::error!("{}", err);
+            process::exit(1);
+        });
+
+    let topic = &settings.interfaces.northbound.kafka.output_1.topic;
+    schemas::register_schemas(&registry, topic, schemas).unwrap_or_else(|err| {
+        log::error!("Failed to register the VES schemas: {}", err);
+        process::exit(1);
+    })
 }
 
 fn main() {
-    env_logger::init();
-    let my_app = MyApp::new_with_prefix("APP");
+    let settings = Settings::new_with_prefix("APP").unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    logging::init(&settings.log, SERVICE_NAME).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    let _trace_guard = trace::init(&settings.trace, SERVICE_NAME).unwrap_or_else(|err| {
+        log::error!("{}", err);
+        process::exit(1);
+    });
+    log::debug!("Effective configuration: {}", config::dump_redacted(&settings));
+
+    let schemas = load_schemas(&settings.schemas).unwrap_or_else(|err| {
+        log::error!("{}", err);
+        process::exit(1);
+    });
+    for (version, file) in &schemas {
+        log::info!("VES API version {} uses schema {}", version, file.source.path.display());
+    }
+    let schema_ids = register_schemas(&settings, &schemas);
 
-    let producer = create_kafka_producer(&my_app);
-    let p = Arc::clone(&producer);
     let (tx, rx) = mpsc::channel();
-    let topic = my_app.get_param("interfaces.northbound.kafka.output_1.topic");
-    let app_state = Arc::new(Mutex::new(AppState{ topic, tx, schemas: read_schemas(&my_app.config) }));
-    let http_thread = start_http_server(&my_app, Arc::clone(&app_state));
+    let (failures_tx, failures_rx) = mpsc::channel();
+    let dead_letter_topic = settings.interfaces.northbound.kafka.dead_letter.as_ref().map(|x| x.topic.clone());
+    let spool = open_spool(&settings);
+    let spool_metrics = spool.as_ref().map(Spool::metrics);
+    let fallback = Arc::new(Fallback::new(tx.clone(), failures_tx, dead_letter_topic.clone(), spool));
+    let producer = create_kafka_producer(&settings, Arc::clone(&fallback));
+    let p = Arc::clone(&producer);
+    let topic = settings.interfaces.northbound.kafka.output_1.topic.clone();
+    let heartbeats = settings.heartbeat.enable.then(|| Arc::new(HeartbeatMonitor::new(&settings.heartbeat)));
+    let app_state = Arc::new(Mutex::new(AppState{
+        topic,
+        tx: tx.clone(),
+        schema_sources: schemas.iter().map(|(version, file)| (version.clone(), file.source.clone())).collect(),
+        schemas: schemas.into_iter().map(|(version, file)| (version, file.schema)).collect(),
+        schema_ids,
+        max_message_size: settings.http.max_message_size,
+        partial_batch: settings.http.partial_batch,
+        dead_letter_topic,
+        spool_metrics,
+        heartbeats: heartbeats.clone(),
+        dedup: settings.dedup.enable.then(|| Deduplicator::new(&settings.dedup)),
+        rate_limiter: settings.rate_limit.enable.then(|| RateLimiter::new(&settings.rate_limit)),
+    }));
+    let http_thread = start_http_server(&settings, Arc::clone(&app_state));
+    if let Some(monitor) = heartbeats {
+        start_heartbeat_supervision(&settings, monitor, Arc::clone(&app_state));
+    }
 
+    let retry_interval = Duration::from_millis(settings.spool.retry_interval_ms);
+    let batch_timeout = Duration::from_millis(settings.kafka.queue_full.batch_timeout_ms);
     let rt = tokio::runtime::Runtime::new().unwrap();
 
-    if my_app.get_param_bool("interfaces.southbound.kafka.enable") {
-        rt.spawn(kafka_consumer::consume_topic_1_and_print(my_app));
+    if settings.interfaces.southbound.kafka.enable {
+        rt.spawn(kafka_consumer::consume_topic_1_and_print(settings));
     }
 
-    rt.spawn(start_kafka_producer(p, rx));
+    rt.spawn(start_kafka_producer(Forwarder::new(p, fallback, failures_rx, retry_interval, batch_timeout), rx));
 
     let _ = http_thread.join();
 
-    log::debug!("{:?}", producer.metrics());
+    log::debug!("{}", serde_json::to_string(&producer.snapshot()).unwrap_or_default());
 }
 
-fn start_http_server(my_app: &MyApp, app_state: Arc<Mutex<AppState>>) -> JoinHandle<()> {
-    let port = my_app.get_param("interfaces.http.port");
-    let _topic = my_app.get_param("interfaces.northbound.kafka.output_1.topic");
-    thread::spawn(|| http_server::start(port, app_state))
+fn start_http_server(settings: &Settings, app_state: Arc<Mutex<AppState>>) -> JoinHandle<()> {
+    let port = settings.interfaces.http.port;
+    let workers = settings.http.workers;
+    thread::spawn(move || http_server::start(port, workers, app_state))
 }
 
-fn create_kafka_producer(my_app: &MyApp) -> Arc<KafkaProducer> {
-    let mut cfg = HashMap::new();
-    cfg.insert(
-        "bootstrap.servers".to_string(),
-        my_app.get_param("kafka.address"),
-    );
-    Arc::new(KafkaProducer::new(cfg))
+/// Raises the faults of the missed heartbeats once in every check interval.
+fn start_heartbeat_supervision(settings: &Settings, monitor: Arc<HeartbeatMonitor>, app_state: Arc<Mutex<AppState>>) {
+    let interval = Duration::from_millis(settings.heartbeat.check_interval_ms);
+    thread::Builder::new()
+        .name("ves:hb".into())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            let faults = monitor.check(Utc::now());
+            let state = app_state.lock().unwrap();
+            for fault in faults {
+                let _ = state.tx.send(fault_record(&state.topic, &fault, &state.schema_ids));
+            }
+        })
+        .expect("Invalid thread name");
+}
+
+fn open_spool(settings: &Settings) -> Option<Spool> {
+    if !settings.spool.enable {
+        return None;
+    }
+
+    Some(Spool::open(&settings.spool.directory, settings.spool.max_size).unwrap_or_else(|err| {
+        log::error!("Failed to open spool `{}`: {}", settings.spool.directory, err);
+        process::exit(1);
+    }))
+}
+
+fn create_kafka_producer(settings: &Settings, fallback: Arc<Fallback>) -> Arc<KafkaProducer> {
+    let producer = KafkaProducer::builder()
+        .brokers([&settings.kafka.address])
+        .queue_full_policy(settings.kafka.queue_full.to_policy())
+        .on_delivery_failure(move |failed| fallback.on_delivery_failure(failed))
+        .build()
+        .unwrap_or_else(|err| {
+            log::error!("{}", err);
+            process::exit(1);
+        });
+    Arc::new(producer)
 }
 
 async fn start_kafka_producer(
-    producer: Arc<KafkaProducer>,
+    forwarder: Forwarder,
     rx: Receiver<ProducerRecord>,
 ) -> JoinHandle<()> {
     thread::Builder::new()
         .name("ves:kf".into())
-        .spawn(move || loop {
-            match rx.recv() {
-                Ok(msg) => {
-                    match producer.send(&msg) {
-                        Ok(_) => log::info!("Message sent to Kafka broker"),
-                        Err((e, _)) => log::warn!("Failed to send message to Kafka broker: {}", e),
-                    };
-                }
-                Err(_) => {
-                    log::info!("Channel closed");
-                    return;
-                }
-            };
-        }).expect("Invalid thread name")
+        .spawn(move || forwarder.run(rx))
+        .expect("Invalid thread name")
 }
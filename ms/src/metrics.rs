@@ -0,0 +1,71 @@
+//! Metrics in the Prometheus text format, served by `GET /metrics`.
+//!
+//! The chart annotates the pod for scraping, see `prometheus` in its values. Only the enabled
+//! features add their metrics.
+
+use std::fmt::Write;
+
+use crate::types::AppState;
+
+/// The `Content-Type` of the text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Writer of the text format.
+#[derive(Default)]
+pub struct MetricsText {
+    text: String,
+}
+
+impl MetricsText {
+    /// Adds a counter, `name` should end with `_total`.
+    pub fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.sample(name, "counter", help, value);
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, value: u64) {
+        self.sample(name, "gauge", help, value);
+    }
+
+    fn sample(&mut self, name: &str, kind: &str, help: &str, value: u64) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+        let _ = writeln!(self.text, "{} {}", name, value);
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+}
+
+/// The metrics of the enabled features.
+pub fn render(state: &AppState) -> String {
+    let mut metrics = MetricsText::default();
+    if let Some(dedup) = &state.dedup {
+        dedup.write_metrics(&mut metrics);
+    }
+    if let Some(limiter) = &state.rate_limiter {
+        limiter.write_metrics(&mut metrics);
+    }
+    metrics.into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_format() {
+        let mut metrics = MetricsText::default();
+        metrics.counter("ves_dedup_duplicates_total", "Duplicate events", 3);
+        metrics.gauge("ves_dedup_entries", "Events in the window", 10);
+        assert_eq!(
+            metrics.into_string(),
+            "# HELP ves_dedup_duplicates_total Duplicate events\n\
+             # TYPE ves_dedup_duplicates_total counter\n\
+             ves_dedup_duplicates_total 3\n\
+             # HELP ves_dedup_entries Events in the window\n\
+             # TYPE ves_dedup_entries gauge\n\
+             ves_dedup_entries 10\n"
+        );
+    }
+}
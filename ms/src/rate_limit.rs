@@ -0,0 +1,237 @@
+//! Token bucket rate limiting of the incoming events.
+//!
+//! Every client has a bucket of `rate_limit.burst` tokens, refilled with `rate_limit.rate` tokens
+//! per second, and each event of a request takes a token. A request whose body cannot be parsed
+//! takes one. The client is identified by `rate_limit.key`:
+//!
+//! - `client_ip`: the address of the peer
+//! - `user`: the user name of the `Authorization: Basic` header; it is not verified here, so it
+//!   is meant for deployments behind an authenticating proxy
+//! - `reporting_entity`: the `reportingEntityName` of each event, the events of a batch are
+//!   charged to the bucket of their own entity
+//!
+//! If the key is missing from the request or the event, the client IP is used instead. A request
+//! without enough tokens is rejected with 429 and `Retry-After`, see [`too_many_requests`].
+//!
+//! A batch of more events than the burst is accepted from a full bucket. Its balance goes
+//! negative then, and the client waits until it is refilled, so that the rate holds on average.
+//!
+//! [`too_many_requests`]: crate::ves::too_many_requests
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{metrics::MetricsText, settings::RateLimitSettings};
+
+/// What identifies the client of a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    ClientIp,
+    User,
+    ReportingEntity,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// The number of events rejected
+    limited: u64,
+}
+
+pub struct RateLimiter {
+    key: RateLimitKey,
+    rate: f64,
+    burst: f64,
+    max_clients: usize,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    allowed: AtomicU64,
+    limited: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Self {
+        Self {
+            key: settings.key,
+            rate: settings.rate,
+            burst: settings.burst as f64,
+            max_clients: settings.max_clients,
+            buckets: Mutex::new(HashMap::new()),
+            allowed: AtomicU64::new(0),
+            limited: AtomicU64::new(0),
+        }
+    }
+
+    pub fn key(&self) -> RateLimitKey {
+        self.key
+    }
+
+    /// Takes a token for each of the `events` from the bucket of the client, or returns how long
+    /// to wait until there are enough of them.
+    ///
+    /// The whole count is taken, at least one token. A request of more events than the burst
+    /// needs a full bucket and leaves a negative balance.
+    pub fn acquire(&self, client: &str, events: usize, now: Instant) -> Result<(), Duration> {
+        self.acquire_all(&[(client, events)], now)
+    }
+
+    /// Like [`Self::acquire`] for the events of several clients in one request. Either every
+    /// client gets its tokens or none does, with the longest wait of them.
+    pub fn acquire_all(&self, requests: &[(&str, usize)], now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut wait = Duration::ZERO;
+        for &(client, events) in requests {
+            if !buckets.contains_key(client) && buckets.len() >= self.max_clients {
+                Self::evict(&mut buckets, |x| requests.iter().all(|(client, _)| *client != x));
+            }
+
+            let bucket = buckets
+                .entry(client.to_string())
+                .or_insert(Bucket { tokens: self.burst, updated: now, limited: 0 });
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+            bucket.updated = now;
+
+            let needed = (events.max(1) as f64).min(self.burst);
+            if bucket.tokens < needed {
+                wait = wait.max(Duration::from_secs_f64((needed - bucket.tokens) / self.rate));
+            }
+        }
+
+        let total = requests.iter().map(|(_, events)| *events as u64).sum();
+        for &(client, events) in requests {
+            let Some(bucket) = buckets.get_mut(client) else {
+                continue;
+            };
+            if wait.is_zero() {
+                bucket.tokens -= events.max(1) as f64;
+            } else {
+                bucket.limited += events as u64;
+            }
+        }
+
+        if wait.is_zero() {
+            self.allowed.fetch_add(total, Ordering::Relaxed);
+            Ok(())
+        } else {
+            self.limited.fetch_add(total, Ordering::Relaxed);
+            Err(wait)
+        }
+    }
+
+    /// Removes the least recently used bucket of the clients which may be evicted.
+    fn evict(buckets: &mut HashMap<String, Bucket>, evictable: impl Fn(&str) -> bool) {
+        let oldest = buckets
+            .iter()
+            .filter(|(client, _)| evictable(client))
+            .min_by_key(|(_, x)| x.updated)
+            .map(|(client, _)| client.clone());
+        if let Some(client) = oldest {
+            buckets.remove(&client);
+        }
+    }
+
+    /// The allowed and the rejected events, with the rejected ones by client.
+    pub fn to_json(&self) -> Value {
+        let mut clients = self
+            .buckets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, x)| x.limited > 0)
+            .map(|(client, x)| (client.clone(), Value::from(x.limited)))
+            .collect::<Vec<_>>();
+        clients.sort_by(|a, b| a.0.cmp(&b.0));
+
+        json!({
+            "allowedEvents": self.allowed.load(Ordering::Relaxed),
+            "limitedEvents": self.limited.load(Ordering::Relaxed),
+            "limitedByClient": clients.into_iter().collect::<serde_json::Map<_, _>>(),
+        })
+    }
+
+    pub fn write_metrics(&self, metrics: &mut MetricsText) {
+        let clients = self.buckets.lock().unwrap().len() as u64;
+        metrics.gauge("ves_rate_limit_clients", "Clients with a token bucket", clients);
+        metrics.counter(
+            "ves_rate_limit_allowed_events_total",
+            "Events within the rate limit",
+            self.allowed.load(Ordering::Relaxed),
+        );
+        metrics.counter(
+            "ves_rate_limit_limited_events_total",
+            "Events rejected by the rate limit",
+            self.limited.load(Ordering::Relaxed),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(rate: f64, burst: u64, max_clients: usize) -> RateLimiter {
+        RateLimiter::new(&RateLimitSettings { enable: true, key: RateLimitKey::ClientIp, rate, burst, max_clients })
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = limiter(10.0, 20, 100);
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire("a", 15, start), Ok(()));
+        assert_eq!(limiter.acquire("a", 10, start), Err(Duration::from_millis(500)));
+        assert_eq!(limiter.acquire("b", 10, start), Ok(()));
+        assert_eq!(limiter.acquire("a", 10, start + Duration::from_millis(500)), Ok(()));
+        // More than the burst needs a full bucket and leaves a debt of 80 tokens
+        assert!(limiter.acquire("c", 100, start).is_ok());
+        assert_eq!(limiter.acquire("c", 100, start + Duration::from_secs(1)), Err(Duration::from_secs(9)));
+        assert!(limiter.acquire("c", 1, start + Duration::from_secs(8)).is_err());
+        assert!(limiter.acquire("c", 1, start + Duration::from_secs(9)).is_ok());
+        // A request without events, e.g., an unparseable one, costs a token
+        assert!(limiter.acquire("d", 0, start).is_ok());
+
+        assert_eq!(
+            limiter.to_json(),
+            json!({ "allowedEvents": 136, "limitedEvents": 111, "limitedByClient": { "a": 10, "c": 101 } })
+        );
+
+        let mut metrics = MetricsText::default();
+        limiter.write_metrics(&mut metrics);
+        assert!(metrics.into_string().contains("\nves_rate_limit_limited_events_total 111\n"));
+    }
+
+    #[test]
+    fn test_acquire_all() {
+        let limiter = limiter(1.0, 10, 100);
+        let start = Instant::now();
+
+        assert!(limiter.acquire("a", 8, start).is_ok());
+        // `b` has enough tokens, but `a` does not, so neither is charged
+        assert_eq!(limiter.acquire_all(&[("a", 5), ("b", 5)], start), Err(Duration::from_secs(3)));
+        assert!(limiter.acquire("b", 10, start).is_ok());
+        assert!(limiter.acquire_all(&[("a", 5), ("c", 5)], start + Duration::from_secs(3)).is_ok());
+    }
+
+    #[test]
+    fn test_eviction() {
+        let limiter = limiter(1.0, 1, 2);
+        let start = Instant::now();
+
+        assert!(limiter.acquire("a", 1, start).is_ok());
+        assert!(limiter.acquire("b", 1, start + Duration::from_millis(1)).is_ok());
+        assert!(limiter.acquire("c", 1, start + Duration::from_millis(2)).is_ok());
+        // The bucket of `a` was evicted, so it is full again
+        assert!(limiter.acquire("a", 1, start + Duration::from_millis(3)).is_ok());
+        assert!(limiter.acquire("c", 1, start + Duration::from_millis(4)).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+//! Loading of the VES JSON schemas.
+//!
+//! The schemas are listed under `schemas.apiVersion`, or discovered in `schemas.directory`. The
+//! VES API version of a discovered file is taken from its name, `CommonEventFormat_X.Y.Z.json`,
+//! mapped by [`CEF_VERSIONS`]. If the name does not match or the version is not in the table, the
+//! latest `commonEventHeader.vesEventListenerVersion` allowed by the schema is used. The listed
+//! schemas take precedence over the discovered ones of the same version.
+//!
+//! Every file is checked, and all the bad ones are reported together.
+//!
+//! With the schema registry, each VES major version has its own subject, see [`schema_subject`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use jsonschema::JSONSchema;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use ers::{
+    config::ConfigError,
+    kafka::{schema_registry::value_subject, RegistryError, Schema, SchemaRegistry},
+    SemVer,
+};
+
+use crate::settings::SchemaSettings;
+
+const FILE_PREFIX: &str = "CommonEventFormat_";
+const FILE_EXTENSION: &str = "json";
+
+/// Common Event Format versions and the VES API version they define.
+pub const CEF_VERSIONS: [(&str, &str); 7] = [
+    ("28.4.1", "5.4.1"),
+    ("30", "7.0.0"),
+    ("30.0.1", "7.0.1"),
+    ("30.1", "7.1.0"),
+    ("30.1.1", "7.1.1"),
+    ("30.2", "7.2.0"),
+    ("30.2.1", "7.2.1"),
+];
+
+/// Where a schema was loaded from, reported by `GET /schemas`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaSource {
+    pub path: PathBuf,
+    /// Hex SHA-256 of the file
+    pub sha256: String,
+    /// The content of the file
+    #[serde(skip)]
+    pub definition: String,
+}
+
+impl SchemaSource {
+    fn new(path: PathBuf, definition: String) -> Self {
+        let sha256 = Sha256::digest(definition.as_bytes()).iter().map(|x| format!("{:02x}", x)).collect();
+        Self { path, sha256, definition }
+    }
+}
+
+/// A loaded and compiled schema.
+pub struct SchemaFile {
+    pub source: SchemaSource,
+    pub schema: JSONSchema,
+}
+
+/// Parses a version with missing parts as zero, e.g., `7.2` as `7.2.0`.
+fn parse_version(version: &str) -> Option<SemVer> {
+    let mut parts = version.split('.').map(|x| x.parse::<u16>());
+    let version = SemVer {
+        major: parts.next()?.ok()?,
+        minor: parts.next().unwrap_or(Ok(0)).ok()?,
+        patch: parts.next().unwrap_or(Ok(0)).ok()?,
+    };
+    parts.next().is_none().then_some(version)
+}
+
+/// The VES API version of a schema, by the file name or the content, see the module docs.
+pub fn api_version(file_name: &str, schema: &Value) -> Option<SemVer> {
+    let by_name = file_name
+        .strip_prefix(FILE_PREFIX)
+        .and_then(|x| x.strip_suffix(".json"))
+        .and_then(|cef| CEF_VERSIONS.iter().find(|(x, _)| *x == cef))
+        .and_then(|(_, api)| api.parse().ok());
+
+    by_name.or_else(|| {
+        schema
+            .pointer("/definitions/commonEventHeader/properties/vesEventListenerVersion/enum")?
+            .as_array()?
+            .iter()
+            .filter_map(|x| parse_version(x.as_str()?))
+            .max()
+    })
+}
+
+fn read_json(path: &Path) -> Result<(String, Value), String> {
+    let definition = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let json = serde_json::from_str(&definition).map_err(|err| format!("{}: invalid JSON: {}", path.display(), err))?;
+    Ok((definition, json))
+}
+
+fn compile(path: PathBuf, definition: String, json: &Value) -> Result<SchemaFile, String> {
+    let schema = JSONSchema::compile(json)
+        .map_err(|err| format!("{}: invalid JSON schema: {}", path.display(), err))?;
+    Ok(SchemaFile { source: SchemaSource::new(path, definition), schema })
+}
+
+/// The `.json` files of the directory by their API version.
+fn discover(directory: &Path, errors: &mut Vec<String>) -> BTreeMap<SemVer, SchemaFile> {
+    let mut paths = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|x| x.ok().map(|x| x.path()))
+            .filter(|x| x.is_file() && x.extension().is_some_and(|x| x == FILE_EXTENSION))
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            errors.push(format!("{}: {}", directory.display(), err));
+            return BTreeMap::new();
+        }
+    };
+    paths.sort();
+
+    let mut schemas = BTreeMap::<SemVer, SchemaFile>::new();
+    for path in paths {
+        let (definition, json) = match read_json(&path) {
+            Ok(x) => x,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let Some(version) = api_version(&file_name, &json) else {
+            errors.push(format!(
+                "{}: unknown VES API version, list it under schemas.apiVersion",
+                path.display()
+            ));
+            continue;
+        };
+        if let Some(other) = schemas.get(&version) {
+            errors.push(format!(
+                "{}: VES API version {} is already defined by {}",
+                path.display(),
+                version,
+                other.source.path.display()
+            ));
+            continue;
+        }
+
+        match compile(path, definition, &json) {
+            Ok(schema) => {
+                schemas.insert(version, schema);
+            }
+            Err(err) => errors.push(err),
+        }
+    }
+
+    schemas
+}
+
+/// Loads the listed and the discovered schemas by their VES API version.
+pub fn load_schemas(settings: &SchemaSettings) -> Result<BTreeMap<SemVer, SchemaFile>, ConfigError> {
+    let mut errors = Vec::new();
+    let mut schemas = match &settings.directory {
+        Some(directory) => discover(Path::new(directory), &mut errors),
+        None => BTreeMap::new(),
+    };
+
+    let mut listed = settings.api_version.iter().collect::<Vec<_>>();
+    listed.sort();
+    for (version, path) in listed {
+        let Ok(version) = version.parse::<SemVer>() else {
+            errors.push(format!("schemas.apiVersion.{}: invalid version, expected major.minor.patch", version));
+            continue;
+        };
+        let path = PathBuf::from(path);
+        match read_json(&path).and_then(|(definition, json)| compile(path, definition, &json)) {
+            Ok(schema) => {
+                if let Some(discovered) = schemas.insert(version, schema) {
+                    log::info!("{} is overridden by schemas.apiVersion", discovered.source.path.display());
+                }
+            }
+            Err(err) => errors.push(err),
+        }
+    }
+
+    ConfigError::from_errors(errors)?;
+    if schemas.is_empty() {
+        return Err(ConfigError::new("No VES schemas found"));
+    }
+    Ok(schemas)
+}
+
+/// The registry subject of the schemas of a VES major version, e.g., `output_1-v7-value`.
+///
+/// The major versions are incompatible with each other, so they cannot share the subject of the
+/// output topic, whose compatibility check would reject all but the first of them.
+pub fn schema_subject(topic: &str, major: u16) -> String {
+    value_subject(&format!("{}-v{}", topic, major))
+}
+
+/// Registers the schemas of the output topic, returns their IDs by version.
+pub fn register_schemas(
+    registry: &dyn SchemaRegistry,
+    topic: &str,
+    schemas: &BTreeMap<SemVer, SchemaFile>,
+) -> Result<HashMap<SemVer, u32>, RegistryError> {
+    let mut schema_ids = HashMap::new();
+    for (version, file) in schemas {
+        let subject = schema_subject(topic, version.major);
+        let id = registry.register(&subject, &Schema::json(file.source.definition.clone()))?;
+        log::info!("Schema {} registered under `{}` with ID {}", version, subject, id);
+        schema_ids.insert(version.clone(), id);
+    }
+    Ok(schema_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use ers::kafka::MockSchemaRegistry;
+    use serde_json::json;
+
+    use super::*;
+
+    const SCHEMA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/res/schemas/CommonEventFormat_30.2.1.json");
+
+    fn version(x: &str) -> SemVer {
+        x.parse().unwrap()
+    }
+
+    #[test]
+    fn test_api_version() {
+        let versions = json!({ "definitions": { "commonEventHeader": { "properties": {
+            "vesEventListenerVersion": { "enum": ["7.0", "7.1.1", "7.2"] }
+        }}}});
+
+        assert_eq!(api_version("CommonEventFormat_30.1.json", &json!({})), Some(version("7.1.0")));
+        assert_eq!(api_version("CommonEventFormat_28.4.1.json", &versions), Some(version("5.4.1")));
+        assert_eq!(api_version("ves.json", &versions), Some(version("7.2.0")));
+        assert_eq!(api_version("CommonEventFormat_99.json", &json!({})), None);
+    }
+
+    #[test]
+    fn test_load_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::copy(SCHEMA, dir.path().join("CommonEventFormat_30.2.1.json")).unwrap();
+        fs::write(dir.path().join("README.md"), "not a schema").unwrap();
+
+        let settings = SchemaSettings {
+            api_version: HashMap::from([("7.1.1".to_string(), SCHEMA.to_string())]),
+            directory: Some(dir.path().to_string_lossy().into_owned()),
+        };
+        let schemas = load_schemas(&settings).unwrap();
+        assert_eq!(schemas.keys().collect::<Vec<_>>(), [&version("7.1.1"), &version("7.2.1")]);
+        assert_eq!(schemas[&version("7.1.1")].source.path, Path::new(SCHEMA));
+        assert_eq!(schemas[&version("7.1.1")].source.sha256.len(), 64);
+
+        // Detected as 7.2.1 by the version field
+        fs::copy(SCHEMA, dir.path().join("ves.json")).unwrap();
+        let error = load_schemas(&settings).err().unwrap();
+        assert!(error.errors[0].contains("ves.json: VES API version 7.2.1 is already defined by"), "{}", error);
+    }
+
+    #[test]
+    fn test_all_bad_files_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("CommonEventFormat_30.json"), "{").unwrap();
+        fs::write(dir.path().join("CommonEventFormat_30.1.json"), r#"{"type": 5}"#).unwrap();
+        fs::write(dir.path().join("other.json"), "{}").unwrap();
+
+        let settings = SchemaSettings {
+            api_version: HashMap::from([("7.2.1".to_string(), "missing.json".to_string())]),
+            directory: Some(dir.path().to_string_lossy().into_owned()),
+        };
+        let error = load_schemas(&settings).err().unwrap();
+        assert_eq!(error.errors.len(), 4, "{}", error);
+        assert!(error.errors[0].contains("CommonEventFormat_30.1.json: invalid JSON schema"));
+        assert!(error.errors[2].contains("other.json: unknown VES API version"));
+    }
+
+    #[test]
+    fn test_register_schemas() {
+        let settings = SchemaSettings {
+            api_version: HashMap::from([
+                ("5.4.1".to_string(), SCHEMA.replace("30.2.1", "28.4.1")),
+                ("7.1.1".to_string(), SCHEMA.to_string()),
+                ("7.2.1".to_string(), SCHEMA.to_string()),
+            ]),
+            directory: None,
+        };
+        let schemas = load_schemas(&settings).unwrap();
+        let registry = MockSchemaRegistry::default();
+
+        let ids = register_schemas(&registry, "output_1", &schemas).unwrap();
+        assert_eq!(ids, HashMap::from([(version("5.4.1"), 1), (version("7.1.1"), 2), (version("7.2.1"), 2)]));
+        assert_eq!(registry.versions("output_1-v5-value"), [1]);
+        assert_eq!(registry.versions("output_1-v7-value"), [2]);
+        assert!(registry.versions("output_1-value").is_empty());
+    }
+}
@@ -0,0 +1,582 @@
+//! Strongly-typed service configuration.
+//!
+//! The configuration file is deserialized into [`Settings`] once at startup, unknown keys are
+//! rejected and every validation error is reported together, so a typo in `settings.yaml` is
+//! found before the service starts instead of when a code path is first hit.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use ers::{
+    SemVer,
+    config::{ConfigError, ConfigLoader},
+    kafka::QueueFullPolicy,
+    logging::LogSettings,
+    trace::TraceSettings,
+};
+
+use crate::rate_limit::RateLimitKey;
+
+/// Root of the service configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(default)]
+    pub log: LogSettings,
+    #[serde(default)]
+    pub trace: TraceSettings,
+    #[serde(default)]
+    pub http: HttpSettings,
+    #[serde(default)]
+    pub kafka: KafkaSettings,
+    #[serde(default)]
+    pub spool: SpoolSettings,
+    #[serde(default)]
+    pub schema_registry: SchemaRegistrySettings,
+    #[serde(default)]
+    pub heartbeat: HeartbeatSettings,
+    #[serde(default)]
+    pub dedup: DedupSettings,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    pub schemas: SchemaSettings,
+    pub interfaces: InterfacesSettings,
+}
+
+/// Tuning of the HTTP server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSettings {
+    /// The number of HTTP worker threads
+    pub workers: usize,
+    /// Maximum size of a request body in bytes, both compressed and decompressed
+    pub max_message_size: usize,
+    /// Accept the valid events of a batch and reject only the invalid ones, instead of rejecting
+    /// the whole batch
+    pub partial_batch: bool,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            max_message_size: 2 * 1024 * 1024,
+            partial_batch: false,
+        }
+    }
+}
+
+/// Common Kafka client settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaSettings {
+    /// Bootstrap servers, e.g., `localhost:9092`
+    pub address: String,
+    pub queue_full: QueueFullSettings,
+}
+
+impl Default for KafkaSettings {
+    fn default() -> Self {
+        Self {
+            address: String::from("localhost:9092"),
+            queue_full: QueueFullSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueFullMode {
+    Fail,
+    Block,
+    Retry,
+}
+
+/// What to do when the producer queue is full, see [`QueueFullPolicy`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueFullSettings {
+    pub policy: QueueFullMode,
+    /// How long to wait for room in the queue with `block` in milliseconds
+    pub timeout_ms: u64,
+    /// The number of retries with `retry`
+    pub retries: u32,
+    /// Backoff before the first retry in milliseconds, doubled after each retry
+    pub backoff_ms: u64,
+    /// Maximum backoff in milliseconds
+    pub max_backoff_ms: u64,
+    /// How long a batch of records may wait for room in the queue in total, in milliseconds
+    pub batch_timeout_ms: u64,
+}
+
+impl Default for QueueFullSettings {
+    fn default() -> Self {
+        Self {
+            policy: QueueFullMode::Block,
+            timeout_ms: 1000,
+            retries: 5,
+            backoff_ms: 10,
+            max_backoff_ms: 1000,
+            batch_timeout_ms: 5000,
+        }
+    }
+}
+
+impl QueueFullSettings {
+    pub fn to_policy(&self) -> QueueFullPolicy {
+        match self.policy {
+            QueueFullMode::Fail => QueueFullPolicy::FailFast,
+            QueueFullMode::Block => QueueFullPolicy::Block { timeout: Duration::from_millis(self.timeout_ms) },
+            QueueFullMode::Retry => QueueFullPolicy::Retry {
+                retries: self.retries,
+                initial_backoff: Duration::from_millis(self.backoff_ms),
+                max_backoff: Duration::from_millis(self.max_backoff_ms),
+            },
+        }
+    }
+}
+
+/// Local disk spool of the events while Kafka is unavailable, see [`crate::spool`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpoolSettings {
+    pub enable: bool,
+    /// Directory of the spooled records, created if it does not exist. It must be on a volume
+    /// which outlives the container, see `spool` in the chart values.
+    pub directory: String,
+    /// Maximum total size of the spooled records in bytes
+    pub max_size: u64,
+    /// Time between two attempts to send while Kafka is unavailable in milliseconds
+    pub retry_interval_ms: u64,
+}
+
+impl Default for SpoolSettings {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            directory: String::from("/var/lib/ves/spool"),
+            max_size: 1024 * 1024 * 1024,
+            retry_interval_ms: 5000,
+        }
+    }
+}
+
+/// Schema registry of the produced events.
+///
+/// The schemas are registered under the subject `<output topic>-v<major>-value` at startup and
+/// the events are produced in the registry wire format, prefixed with the ID of their schema.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchemaRegistrySettings {
+    pub enable: bool,
+    /// Base URL of the registry, e.g., `http://localhost:8081`
+    pub url: String,
+    /// Timeout of a registry request in milliseconds
+    pub timeout_ms: u64,
+}
+
+impl Default for SchemaRegistrySettings {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            url: String::from("http://localhost:8081"),
+            timeout_ms: 5000,
+        }
+    }
+}
+
+/// Supervision of the heartbeats of the sources, see [`crate::heartbeat`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatSettings {
+    pub enable: bool,
+    /// The number of intervals without a heartbeat before the fault is raised
+    pub missed_intervals: u32,
+    /// Interval of the heartbeats without `heartbeatInterval` in seconds
+    pub default_interval_s: u64,
+    /// The longest `heartbeatInterval` accepted in seconds, longer ones are shortened to it
+    pub max_interval_s: u64,
+    /// Maximum number of tracked sources, the one with the oldest heartbeat is dropped beyond
+    pub max_sources: usize,
+    /// Time between two checks of the missed heartbeats in milliseconds
+    pub check_interval_ms: u64,
+    /// `reportingEntityName` of the fault events
+    pub reporting_entity_name: String,
+}
+
+impl Default for HeartbeatSettings {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            missed_intervals: 3,
+            default_interval_s: 60,
+            max_interval_s: 86400,
+            max_sources: 10000,
+            check_interval_ms: 1000,
+            reporting_entity_name: String::from("ves"),
+        }
+    }
+}
+
+/// Deduplication of the resent events, see [`crate::dedup`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DedupSettings {
+    pub enable: bool,
+    /// How long an event is remembered in milliseconds
+    pub ttl_ms: u64,
+    /// The maximum number of remembered events
+    pub max_entries: usize,
+    /// Send the duplicates to the dead-letter topic instead of dropping them
+    pub dead_letter: bool,
+}
+
+impl Default for DedupSettings {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            ttl_ms: 5 * 60 * 1000,
+            max_entries: 100_000,
+            dead_letter: false,
+        }
+    }
+}
+
+/// Rate limiting of the incoming events by client, see [`crate::rate_limit`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSettings {
+    pub enable: bool,
+    /// `client_ip`, `user` or `reporting_entity`
+    pub key: RateLimitKey,
+    /// Events per second of a client
+    pub rate: f64,
+    /// The number of events a client can send at once
+    pub burst: u64,
+    /// The maximum number of clients tracked, the least recently seen one is forgotten
+    pub max_clients: usize,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            key: RateLimitKey::ClientIp,
+            rate: 1000.0,
+            burst: 2000,
+            max_clients: 10_000,
+        }
+    }
+}
+
+/// Supported VES API versions and the JSON schema file of each, see [`crate::schemas`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaSettings {
+    #[serde(rename = "apiVersion", default)]
+    pub api_version: HashMap<String, String>,
+    /// Directory of schemas, their versions are detected
+    #[serde(default)]
+    pub directory: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterfacesSettings {
+    #[serde(default)]
+    pub http: HttpInterface,
+    pub northbound: NorthboundInterfaces,
+    #[serde(default)]
+    pub southbound: SouthboundInterfaces,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpInterface {
+    pub port: u16,
+}
+
+impl Default for HttpInterface {
+    fn default() -> Self {
+        Self { port: 8080 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NorthboundInterfaces {
+    pub kafka: NorthboundKafka,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NorthboundKafka {
+    pub output_1: KafkaOutput,
+    /// Topic of the rejected and the undeliverable events, see [`crate::dead_letter`]
+    #[serde(default)]
+    pub dead_letter: Option<KafkaOutput>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaOutput {
+    pub topic: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SouthboundInterfaces {
+    pub kafka: SouthboundKafka,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SouthboundKafka {
+    pub enable: bool,
+    pub input_1: KafkaInput,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaInput {
+    pub enable: bool,
+    pub topic: String,
+    pub group_id: String,
+    pub enable_auto_commit: bool,
+}
+
+impl Settings {
+    /// Loads the configuration with [`ConfigLoader`] and validates it.
+    ///
+    /// Environment variables `<PREFIX>_<SECTION>__<KEY>` override the file, e.g.,
+    /// `APP_INTERFACES__HTTP__PORT=9090` sets `interfaces.http.port`.
+    pub fn new_with_prefix(prefix: &str) -> Result<Self, ConfigError> {
+        Self::from_loader(ConfigLoader::new(prefix))
+    }
+
+    /// Loads the configuration from the given sources and validates it.
+    pub fn from_loader(loader: ConfigLoader) -> Result<Self, ConfigError> {
+        let settings: Self = loader.load()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Checks the semantic constraints which cannot be expressed by the types.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+
+        if self.http.workers == 0 {
+            errors.push("http.workers must be at least 1".to_string());
+        }
+
+        if self.http.max_message_size == 0 {
+            errors.push("http.max_message_size must be at least 1".to_string());
+        }
+
+        if self.kafka.address.is_empty() {
+            errors.push("kafka.address must not be empty".to_string());
+        }
+
+        if self.kafka.queue_full.backoff_ms > self.kafka.queue_full.max_backoff_ms {
+            errors.push("kafka.queue_full.backoff_ms must not be above max_backoff_ms".to_string());
+        }
+
+        if self.spool.enable {
+            if self.spool.directory.is_empty() {
+                errors.push("spool.directory must not be empty".to_string());
+            }
+            if self.spool.max_size == 0 {
+                errors.push("spool.max_size must be at least 1".to_string());
+            }
+        }
+
+        if self.schema_registry.enable && self.schema_registry.url.is_empty() {
+            errors.push("schema_registry.url must not be empty".to_string());
+        }
+
+        if self.heartbeat.enable {
+            if self.heartbeat.missed_intervals == 0 {
+                errors.push("heartbeat.missed_intervals must be at least 1".to_string());
+            }
+            if self.heartbeat.default_interval_s == 0 {
+                errors.push("heartbeat.default_interval_s must be at least 1".to_string());
+            }
+            if self.heartbeat.default_interval_s > self.heartbeat.max_interval_s {
+                errors.push("heartbeat.default_interval_s must not be above max_interval_s".to_string());
+            }
+            if self.heartbeat.max_sources == 0 {
+                errors.push("heartbeat.max_sources must be at least 1".to_string());
+            }
+            if self.heartbeat.check_interval_ms == 0 {
+                errors.push("heartbeat.check_interval_ms must be at least 1".to_string());
+            }
+        }
+
+        if self.dedup.enable {
+            if self.dedup.ttl_ms == 0 {
+                errors.push("dedup.ttl_ms must be at least 1".to_string());
+            }
+            if self.dedup.max_entries == 0 {
+                errors.push("dedup.max_entries must be at least 1".to_string());
+            }
+        }
+
+        if self.rate_limit.enable {
+            if !self.rate_limit.rate.is_finite() || self.rate_limit.rate <= 0.0 {
+                errors.push("rate_limit.rate must be positive".to_string());
+            }
+            if self.rate_limit.burst == 0 {
+                errors.push("rate_limit.burst must be at least 1".to_string());
+            }
+            if self.rate_limit.max_clients == 0 {
+                errors.push("rate_limit.max_clients must be at least 1".to_string());
+            }
+        }
+
+        if self.interfaces.http.port == 0 {
+            errors.push("interfaces.http.port must not be 0".to_string());
+        }
+
+        if self.interfaces.northbound.kafka.output_1.topic.is_empty() {
+            errors.push("interfaces.northbound.kafka.output_1.topic must not be empty".to_string());
+        }
+
+        if self.interfaces.northbound.kafka.dead_letter.as_ref().is_some_and(|x| x.topic.is_empty()) {
+            errors.push("interfaces.northbound.kafka.dead_letter.topic must not be empty".to_string());
+        }
+
+        let input = &self.interfaces.southbound.kafka.input_1;
+        if self.interfaces.southbound.kafka.enable && input.enable {
+            if input.topic.is_empty() {
+                errors.push("interfaces.southbound.kafka.input_1.topic must not be empty".to_string());
+            }
+            if input.group_id.is_empty() {
+                errors.push("interfaces.southbound.kafka.input_1.group_id must not be empty".to_string());
+            }
+        }
+
+        match &self.schemas.directory {
+            Some(directory) if !Path::new(directory).is_dir() => {
+                errors.push(format!("schemas.directory: directory not found: {}", directory));
+            }
+            None if self.schemas.api_version.is_empty() => {
+                errors.push("schemas.apiVersion must list at least one schema unless schemas.directory is set".to_string());
+            }
+            _ => {}
+        }
+
+        let mut versions = self.schemas.api_version.iter().collect::<Vec<_>>();
+        versions.sort();
+        for (version, path) in versions {
+            if version.parse::<SemVer>().is_err() {
+                errors.push(format!("schemas.apiVersion.{}: invalid version, expected major.minor.patch", version));
+            }
+            if !Path::new(path).is_file() {
+                errors.push(format!("schemas.apiVersion.{}: schema file not found: {}", version, path));
+            }
+        }
+
+        ConfigError::from_errors(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const SCHEMA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/res/schemas/CommonEventFormat_30.2.1.json");
+
+    fn load(yaml: &str) -> Result<Settings, ConfigError> {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("settings.yaml");
+        fs::write(&file, yaml).unwrap();
+        Settings::from_loader(ConfigLoader::new("APP").file(file).env(HashMap::new()))
+    }
+
+    fn minimal() -> String {
+        format!(r#"
+interfaces:
+  northbound:
+    kafka:
+      output_1:
+        topic: output_1
+schemas:
+  apiVersion:
+    7.2.1: "{}"
+"#, SCHEMA)
+    }
+
+    #[test]
+    fn test_defaults() {
+        let settings = load(&minimal()).unwrap();
+        assert_eq!(settings.http.workers, 1);
+        assert_eq!(settings.http.max_message_size, 2 * 1024 * 1024);
+        assert!(!settings.http.partial_batch);
+        assert!(settings.interfaces.northbound.kafka.dead_letter.is_none());
+        assert_eq!(settings.kafka.address, "localhost:9092");
+        assert_eq!(
+            settings.kafka.queue_full.to_policy(),
+            QueueFullPolicy::Block { timeout: Duration::from_secs(1) }
+        );
+        assert!(!settings.spool.enable);
+        assert!(std::path::Path::new(&settings.spool.directory).is_absolute());
+        assert!(!settings.schema_registry.enable);
+        assert!(!settings.heartbeat.enable);
+        assert!(!settings.dedup.enable);
+        assert!(!settings.rate_limit.enable);
+        assert_eq!(settings.interfaces.http.port, 8080);
+        assert!(!settings.interfaces.southbound.kafka.enable);
+    }
+
+    #[test]
+    fn test_unknown_key_rejected() {
+        let yaml = minimal() + "kafak:\n  address: localhost:9092\n";
+        let error = load(&yaml).unwrap_err();
+        assert!(error.errors[0].contains("kafak"));
+    }
+
+    #[test]
+    fn test_schema_directory() {
+        let yaml = r#"
+interfaces:
+  northbound:
+    kafka:
+      output_1:
+        topic: output_1
+schemas:
+  directory: "res/schemas"
+"#.replace("res/schemas", &format!("{}/res/schemas", env!("CARGO_MANIFEST_DIR")));
+        let settings = load(&yaml).unwrap();
+        assert!(settings.schemas.api_version.is_empty());
+
+        let error = load(&yaml.replace("res/schemas", "res/missing")).unwrap_err();
+        assert!(error.errors[0].starts_with("schemas.directory: directory not found"));
+    }
+
+    #[test]
+    fn test_all_errors_reported() {
+        let yaml = r#"
+http:
+  workers: 0
+interfaces:
+  http:
+    port: 0
+  northbound:
+    kafka:
+      output_1:
+        topic: ""
+schemas:
+  apiVersion:
+    "7.2": "missing.json"
+"#;
+        let error = load(yaml).unwrap_err();
+        assert_eq!(error.errors.len(), 5);
+    }
+}
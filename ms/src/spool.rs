@@ -0,0 +1,319 @@
+//! Local disk spool of the records while Kafka is unavailable.
+//!
+//! Each record is a file in the spool directory named by its sequence number, so the records
+//! survive a restart and are replayed in the order they were spooled. A file is written under a
+//! temporary name and renamed, so a crash never leaves a partial record behind.
+//!
+//! The total size of the files is capped, a record which does not fit is rejected with
+//! [`SpoolError::Full`].
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use serde_json::{json, Value};
+
+use ers::kafka::ProducerRecord;
+
+const EXTENSION: &str = "rec";
+
+#[derive(Debug)]
+pub enum SpoolError {
+    /// The record does not fit into the size limit.
+    Full,
+    Io(io::Error),
+}
+
+impl fmt::Display for SpoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpoolError::Full => write!(f, "Spool is full"),
+            SpoolError::Io(err) => write!(f, "Spool I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SpoolError {}
+
+impl From<io::Error> for SpoolError {
+    fn from(err: io::Error) -> Self {
+        SpoolError::Io(err)
+    }
+}
+
+/// Thread-safe counters of the spool
+#[derive(Default, Debug)]
+pub struct SpoolMetrics {
+    /// The number of records in the spool
+    pub records: AtomicU64,
+    /// The size of the records in the spool in bytes
+    pub bytes: AtomicU64,
+    /// The number of records written to the spool
+    pub spooled_records: AtomicU64,
+    /// The number of records read back from the spool to be sent
+    pub replayed_records: AtomicU64,
+    /// The number of records which could not be spooled or read back
+    pub dropped_records: AtomicU64,
+}
+
+impl SpoolMetrics {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "records": self.records.load(Ordering::Relaxed),
+            "bytes": self.bytes.load(Ordering::Relaxed),
+            "spooledRecords": self.spooled_records.load(Ordering::Relaxed),
+            "replayedRecords": self.replayed_records.load(Ordering::Relaxed),
+            "droppedRecords": self.dropped_records.load(Ordering::Relaxed),
+        })
+    }
+}
+
+pub struct Spool {
+    directory: PathBuf,
+    max_size: u64,
+    next_seq: u64,
+    /// Sequence number and size of the spooled records, the oldest first
+    files: VecDeque<(u64, u64)>,
+    size: u64,
+    metrics: Arc<SpoolMetrics>,
+}
+
+impl Spool {
+    /// Opens the spool directory, creating it if needed, and picks up the records left there.
+    pub fn open(directory: impl AsRef<Path>, max_size: u64) -> Result<Self, SpoolError> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            let seq = path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .and_then(|x| x.parse::<u64>().ok());
+
+            match seq {
+                Some(seq) if path.extension().is_some_and(|x| x == EXTENSION) => {
+                    files.push((seq, entry.metadata()?.len()));
+                }
+                // Leftover of an interrupted write
+                _ if path.extension().is_some_and(|x| x == "tmp") => fs::remove_file(&path)?,
+                _ => log::warn!("Unexpected file in the spool: {}", path.display()),
+            }
+        }
+        files.sort();
+
+        let spool = Self {
+            next_seq: files.last().map_or(0, |(seq, _)| seq + 1),
+            size: files.iter().map(|(_, size)| size).sum(),
+            files: files.into(),
+            directory,
+            max_size,
+            metrics: Default::default(),
+        };
+        spool.update_metrics();
+
+        if !spool.is_empty() {
+            log::info!("{} records found in the spool", spool.len());
+        }
+
+        Ok(spool)
+    }
+
+    pub fn metrics(&self) -> Arc<SpoolMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Appends the record to the spool.
+    pub fn push(&mut self, record: &ProducerRecord) -> Result<(), SpoolError> {
+        let data = encode(record);
+        let size = data.len() as u64;
+
+        if self.size + size > self.max_size {
+            self.metrics.dropped_records.fetch_add(1, Ordering::Relaxed);
+            return Err(SpoolError::Full);
+        }
+
+        let seq = self.next_seq;
+        let tmp = self.directory.join(format!("{:020}.tmp", seq));
+        fs::write(&tmp, &data)
+            .and_then(|_| fs::rename(&tmp, self.path(seq)))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&tmp);
+                self.metrics.dropped_records.fetch_add(1, Ordering::Relaxed);
+            })?;
+
+        self.next_seq += 1;
+        self.files.push_back((seq, size));
+        self.size += size;
+        self.metrics.spooled_records.fetch_add(1, Ordering::Relaxed);
+        self.update_metrics();
+        Ok(())
+    }
+
+    /// Reads the oldest record, it stays in the spool until [`Spool::pop_front`].
+    ///
+    /// Unreadable records are removed.
+    pub fn front(&mut self) -> Option<ProducerRecord> {
+        while let Some((seq, _)) = self.files.front() {
+            let path = self.path(*seq);
+            match fs::read(&path).ok().and_then(|data| decode(&data)) {
+                Some(record) => return Some(record),
+                None => {
+                    log::error!("Dropping unreadable record from the spool: {}", path.display());
+                    self.metrics.dropped_records.fetch_add(1, Ordering::Relaxed);
+                    self.pop_front();
+                }
+            }
+        }
+        None
+    }
+
+    /// Removes the oldest record.
+    pub fn pop_front(&mut self) {
+        let Some((seq, size)) = self.files.pop_front() else {
+            return;
+        };
+
+        if let Err(err) = fs::remove_file(self.path(seq)) {
+            log::warn!("Failed to remove record from the spool: {}", err);
+        }
+        self.size -= size;
+        self.update_metrics();
+    }
+
+    fn path(&self, seq: u64) -> PathBuf {
+        self.directory.join(format!("{:020}.{}", seq, EXTENSION))
+    }
+
+    fn update_metrics(&self) {
+        self.metrics.records.store(self.files.len() as u64, Ordering::Relaxed);
+        self.metrics.bytes.store(self.size, Ordering::Relaxed);
+    }
+}
+
+fn put(data: &mut Vec<u8>, bytes: &[u8]) {
+    data.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    data.extend_from_slice(bytes);
+}
+
+/// Topic, key, headers and payload, each length-prefixed.
+fn encode(record: &ProducerRecord) -> Vec<u8> {
+    let mut data = Vec::with_capacity(record.payload.len() + 256);
+    put(&mut data, record.topic.as_bytes());
+    put(&mut data, &record.key);
+    data.extend_from_slice(&(record.headers.len() as u32).to_be_bytes());
+    for (key, value) in &record.headers {
+        put(&mut data, key.as_bytes());
+        put(&mut data, value);
+    }
+    put(&mut data, &record.payload);
+    data
+}
+
+fn decode(mut data: &[u8]) -> Option<ProducerRecord> {
+    fn take_u32(data: &mut &[u8]) -> Option<u32> {
+        let (len, rest) = data.split_first_chunk::<4>()?;
+        *data = rest;
+        Some(u32::from_be_bytes(*len))
+    }
+
+    fn take<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+        let len = take_u32(data)? as usize;
+        let (bytes, rest) = data.split_at_checked(len)?;
+        *data = rest;
+        Some(bytes)
+    }
+
+    let topic = String::from_utf8(take(&mut data)?.to_vec()).ok()?;
+    let key = take(&mut data)?.to_vec();
+    let mut headers = HashMap::new();
+    for _ in 0..take_u32(&mut data)? {
+        let key = String::from_utf8(take(&mut data)?.to_vec()).ok()?;
+        headers.insert(key, take(&mut data)?.to_vec());
+    }
+    let payload = take(&mut data)?.to_vec();
+
+    data.is_empty().then_some(ProducerRecord { key, topic, headers, payload })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(payload: &str) -> ProducerRecord {
+        ProducerRecord {
+            key: "test".into(),
+            topic: "output_1".into(),
+            headers: HashMap::from([("traceparent".to_string(), b"00-01".to_vec())]),
+            payload: payload.into(),
+        }
+    }
+
+    #[test]
+    fn test_replay_in_order_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 1024).unwrap();
+        spool.push(&record("1")).unwrap();
+        spool.push(&record("2")).unwrap();
+        drop(spool);
+
+        let mut spool = Spool::open(dir.path(), 1024).unwrap();
+        assert_eq!(spool.metrics().records.load(Ordering::Relaxed), 2);
+        spool.push(&record("3")).unwrap();
+
+        let mut payloads = Vec::new();
+        while let Some(record) = spool.front() {
+            assert_eq!(record.headers["traceparent"], b"00-01");
+            payloads.push(String::from_utf8(record.payload).unwrap());
+            spool.pop_front();
+        }
+        assert_eq!(payloads, ["1", "2", "3"]);
+        assert_eq!(spool.metrics().bytes.load(Ordering::Relaxed), 0);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let size = encode(&record("1")).len() as u64;
+        let mut spool = Spool::open(dir.path(), 2 * size).unwrap();
+
+        spool.push(&record("1")).unwrap();
+        spool.push(&record("2")).unwrap();
+        assert!(matches!(spool.push(&record("3")), Err(SpoolError::Full)));
+
+        spool.pop_front();
+        assert!(spool.push(&record("3")).is_ok());
+        assert_eq!(spool.metrics().dropped_records.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_unreadable_record_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 1024).unwrap();
+        spool.push(&record("1")).unwrap();
+        spool.push(&record("2")).unwrap();
+        fs::write(spool.path(0), b"garbage").unwrap();
+
+        assert_eq!(spool.front().unwrap().payload, b"2");
+        assert_eq!(spool.len(), 1);
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
 
 use ers::SemVer;
@@ -6,9 +7,29 @@ use ers::kafka::ProducerRecord;
 
 use jsonschema::JSONSchema;
 
+use crate::{dedup::Deduplicator, heartbeat::HeartbeatMonitor, rate_limit::RateLimiter, schemas::SchemaSource, spool::SpoolMetrics};
+
 // #[derive(Clone)]
 pub struct AppState {
     pub topic: String,
     pub tx: Sender<ProducerRecord>,
     pub schemas: HashMap<SemVer, JSONSchema>,
+    /// The files of the schemas, see `GET /schemas`
+    pub schema_sources: BTreeMap<SemVer, SchemaSource>,
+    /// Schema registry IDs of the schemas, empty if the registry is disabled
+    pub schema_ids: HashMap<SemVer, u32>,
+    /// Maximum size of the request body, both compressed and decompressed
+    pub max_message_size: usize,
+    /// Validate the events of a batch one by one, see `http.partial_batch`
+    pub partial_batch: bool,
+    /// Topic of the rejected and the undeliverable events
+    pub dead_letter_topic: Option<String>,
+    /// Metrics of the spool, if it is enabled
+    pub spool_metrics: Option<Arc<SpoolMetrics>>,
+    /// Heartbeat supervision, if it is enabled
+    pub heartbeats: Option<Arc<HeartbeatMonitor>>,
+    /// Deduplication of the accepted events, if it is enabled
+    pub dedup: Option<Deduplicator>,
+    /// Rate limiting of the clients, if it is enabled
+    pub rate_limiter: Option<RateLimiter>,
 }
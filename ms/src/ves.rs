@@ -1,3 +1,5 @@
+use std::fmt;
+
 use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, PartialOrd, Ord)]
@@ -16,7 +18,24 @@ pub enum VesError {
     PolicyException(VesException),
 }
 
-#[allow(dead_code)]
+impl VesError {
+    /// HTTP status code of the response carrying the exception.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            VesError::PolicyException(e) if e.message_id == "POL9003" => 413,
+            VesError::PolicyException(e) if e.message_id == "POL0001" => 429,
+            _ => 400,
+        }
+    }
+}
+
+impl fmt::Display for VesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (VesError::ServiceException(e) | VesError::PolicyException(e)) = self;
+        write!(f, "{}: {}", e.message_id, e.text)
+    }
+}
+
 pub fn message_size_exceeded() -> VesError {
     VesError::PolicyException(
         VesException{
@@ -26,6 +45,16 @@ pub fn message_size_exceeded() -> VesError {
     )
 }
 
+/// The client sent more events than its rate limit, see [`crate::rate_limit`].
+pub fn too_many_requests() -> VesError {
+    VesError::PolicyException(
+        VesException{
+            message_id: "POL0001".to_string(),
+            text: "General policy error (Too many requests)".to_string(),
+        }
+    )
+}
+
 pub fn invalid_api_version() -> VesError {
     VesError::ServiceException(
         VesException{
@@ -44,6 +73,24 @@ pub fn failed_schema_validation() -> VesError {
     )
 }
 
+pub fn unsupported_content_encoding() -> VesError {
+    VesError::ServiceException(
+        VesException{
+            message_id: "SVC0002".to_string(),
+            text: "Bad parameter (Unsupported Content-Encoding)".to_string(),
+        }
+    )
+}
+
+pub fn invalid_compressed_content() -> VesError {
+    VesError::ServiceException(
+        VesException{
+            message_id: "SVC0002".to_string(),
+            text: "Bad parameter (Content cannot be decompressed)".to_string(),
+        }
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::json;
@@ -82,4 +129,16 @@ mod tests {
             ).to_string()
         );
     }
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(message_size_exceeded().status_code(), 413);
+        assert_eq!(too_many_requests().status_code(), 429);
+        assert_eq!(invalid_api_version().status_code(), 400);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(invalid_api_version().to_string(), "SVC0002: Bad parameter (Invalid API version)");
+    }
 }